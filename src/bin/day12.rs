@@ -1,3 +1,4 @@
+use advent_of_code_2023::grid::{Grid, Position};
 use std::collections::HashMap;
 use std::num::ParseIntError;
 use std::str::FromStr;
@@ -22,6 +23,16 @@ impl From<char> for Condition {
     }
 }
 
+impl From<Condition> for char {
+    fn from(value: Condition) -> Self {
+        match value {
+            Condition::Operational => '.',
+            Condition::Broken => '#',
+            Condition::Unknown => '?',
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 struct Row {
     springs: Vec<Condition>,
@@ -29,8 +40,9 @@ struct Row {
 }
 
 impl Row {
-    fn count_possible_arrangements(&self) -> usize {
-        let mut cache: HashMap<(usize, usize, usize), usize> = HashMap::new();
+    // Returns `None` if the count overflows.
+    fn count_possible_arrangements(&self) -> Option<u128> {
+        let mut cache = HashMap::new();
         self.recursive(0, 0, 0, &mut cache)
     }
 
@@ -39,13 +51,25 @@ impl Row {
         spring: usize,
         damaged_count: usize,
         group: usize,
-        cache: &mut HashMap<(usize, usize, usize), usize>,
-    ) -> usize {
+        cache: &mut HashMap<(usize, usize, usize), Option<u128>>,
+    ) -> Option<u128> {
         if let Some(count) = cache.get(&(spring, damaged_count, group)) {
             return *count;
         }
 
-        let mut count = 0;
+        let count = self.count_from(spring, damaged_count, group, cache);
+        cache.insert((spring, damaged_count, group), count);
+        count
+    }
+
+    fn count_from(
+        &self,
+        spring: usize,
+        damaged_count: usize,
+        group: usize,
+        cache: &mut HashMap<(usize, usize, usize), Option<u128>>,
+    ) -> Option<u128> {
+        let mut count: u128 = 0;
         match self.springs.get(spring) {
             // If the spring is operational:
             //      - if the previous spring was operational, just skip to the next one.
@@ -53,7 +77,12 @@ impl Row {
             //        good. If it is, reset the count; if not, terminate this branch.
             Some(Condition::Operational) => {
                 if damaged_count == 0 {
-                    count += self.recursive(spring + 1, damaged_count, group, cache);
+                    count = count.checked_add(self.recursive(
+                        spring + 1,
+                        damaged_count,
+                        group,
+                        cache,
+                    )?)?;
                 } else if damaged_count
                     == self
                         .contiguous_groups
@@ -61,19 +90,35 @@ impl Row {
                         .copied()
                         .unwrap_or_default()
                 {
-                    count += self.recursive(spring + 1, 0, group + 1, cache);
+                    count =
+                        count.checked_add(self.recursive(spring + 1, 0, group + 1, cache)?)?;
                 }
             }
             // If the spring is broken, simply increment the current count for the group.
             Some(Condition::Broken) => {
-                count += self.recursive(spring + 1, damaged_count + 1, group, cache);
+                count = count.checked_add(self.recursive(
+                    spring + 1,
+                    damaged_count + 1,
+                    group,
+                    cache,
+                )?)?;
             }
             // If the condition is unknown, do both of the above.
             Some(Condition::Unknown) => {
-                count += self.recursive(spring + 1, damaged_count + 1, group, cache);
+                count = count.checked_add(self.recursive(
+                    spring + 1,
+                    damaged_count + 1,
+                    group,
+                    cache,
+                )?)?;
 
                 if damaged_count == 0 {
-                    count += self.recursive(spring + 1, damaged_count, group, cache);
+                    count = count.checked_add(self.recursive(
+                        spring + 1,
+                        damaged_count,
+                        group,
+                        cache,
+                    )?)?;
                 } else if damaged_count
                     == self
                         .contiguous_groups
@@ -81,7 +126,8 @@ impl Row {
                         .copied()
                         .unwrap_or_default()
                 {
-                    count += self.recursive(spring + 1, 0, group + 1, cache);
+                    count =
+                        count.checked_add(self.recursive(spring + 1, 0, group + 1, cache)?)?;
                 }
             }
             // If we have reached the end of the row:
@@ -97,26 +143,170 @@ impl Row {
                         .unwrap_or_default()
                     && self.contiguous_groups.get(group + 1).is_none()
                 {
-                    count += 1;
+                    count = 1;
                 }
             }
         };
 
-        cache.insert((spring, damaged_count, group), count);
-        count
+        Some(count)
+    }
+
+    // The number of arrangements, filling the cache used to rank them.
+    fn checked_count(
+        &self,
+        cache: &mut HashMap<(usize, usize, usize), Option<u128>>,
+    ) -> Result<u128, String> {
+        self.recursive(0, 0, 0, cache)
+            .ok_or_else(|| "Too many arrangements.".to_string())
+    }
+
+    // Every arrangement, with broken springs placed as early as possible first.
+    fn arrangements(&self) -> Result<impl Iterator<Item = Vec<Condition>> + '_, String> {
+        let mut cache = HashMap::new();
+        let count = self.checked_count(&mut cache)?;
+        Ok((0..count).filter_map(move |rank| self.arrangement(rank, &mut cache)))
     }
 
-    fn expand(&self) -> Self {
+    // The arrangement at the given rank in the order of `arrangements`, without going through
+    // the previous ones.
+    fn nth_arrangement(&self, rank: u128) -> Result<Option<Vec<Condition>>, String> {
+        let mut cache = HashMap::new();
+        let count = self.checked_count(&mut cache)?;
+        Ok((rank < count)
+            .then(|| self.arrangement(rank, &mut cache))
+            .flatten())
+    }
+
+    // Pick an arrangement uniformly at random.
+    fn sample(&self, rng: &mut XorShift) -> Result<Option<Vec<Condition>>, String> {
+        let mut cache = HashMap::new();
+        Ok(match self.checked_count(&mut cache)? {
+            0 => None,
+            count => self.arrangement(rng.below(count), &mut cache),
+        })
+    }
+
+    // Walk down the memoized counts: making a spring broken leads to the first arrangements,
+    // making it operational to the remaining ones. The rank must be lower than the count, which
+    // did not overflow, and neither do the counts below it then.
+    fn arrangement(
+        &self,
+        mut rank: u128,
+        cache: &mut HashMap<(usize, usize, usize), Option<u128>>,
+    ) -> Option<Vec<Condition>> {
+        let mut arrangement = Vec::with_capacity(self.springs.len());
+        let (mut damaged_count, mut group) = (0, 0);
+
+        for (spring, &condition) in self.springs.iter().enumerate() {
+            let broken = match condition {
+                Condition::Operational => 0,
+                _ => self.recursive(spring + 1, damaged_count + 1, group, cache)?,
+            };
+
+            if rank < broken {
+                arrangement.push(Condition::Broken);
+                damaged_count += 1;
+            } else {
+                rank -= broken;
+                arrangement.push(Condition::Operational);
+                if damaged_count > 0 {
+                    damaged_count = 0;
+                    group += 1;
+                }
+            }
+        }
+
+        Some(arrangement)
+    }
+
+    // Settle the unknown springs which are in the same condition in every arrangement. Returns
+    // `None` if there is no arrangement at all.
+    fn deduce(&self) -> Option<Vec<Condition>> {
+        if self.count_possible_arrangements() == Some(0) {
+            return None;
+        }
+
+        let mut row = self.clone();
+        for idx in 0..row.springs.len() {
+            if row.springs[idx] != Condition::Unknown {
+                continue;
+            }
+
+            row.springs[idx] = Condition::Broken;
+            let broken = row.count_possible_arrangements();
+            row.springs[idx] = Condition::Operational;
+            let operational = row.count_possible_arrangements();
+
+            row.springs[idx] = match (broken, operational) {
+                (Some(0), _) => Condition::Operational,
+                (_, Some(0)) => Condition::Broken,
+                _ => Condition::Unknown,
+            };
+        }
+
+        Some(row.springs)
+    }
+
+    // Same as `count_possible_arrangements`, but iterative and over arrays so that it scales to
+    // long unfolded rows. Returns `None` if the count overflows.
+    fn count_arrangements(&self) -> Option<u128> {
+        let springs = self.springs.len();
+
+        // The number of operational springs before each spring, to check whether a group fits.
+        let mut operational = vec![0; springs + 1];
+        for (idx, &condition) in self.springs.iter().enumerate() {
+            operational[idx + 1] =
+                operational[idx] + usize::from(condition == Condition::Operational);
+        }
+
+        // `next[spring]` is the number of arrangements of the springs from `spring` onwards with
+        // the groups after the current one, knowing that the previous spring is not broken. Past
+        // the last group, that means no broken spring is left.
+        let mut next = vec![1u128; springs + 2];
+        for spring in (0..springs).rev() {
+            if self.springs[spring] == Condition::Broken || next[spring + 1] == 0 {
+                next[spring] = 0;
+            }
+        }
+
+        for &size in self.contiguous_groups.iter().rev() {
+            let mut current = vec![0u128; springs + 2];
+            for spring in (0..springs).rev() {
+                let mut count = 0u128;
+                if self.springs[spring] != Condition::Broken {
+                    count = current[spring + 1];
+                }
+
+                let end = spring + size;
+                if end <= springs
+                    && operational[end] == operational[spring]
+                    && self.springs.get(end) != Some(&Condition::Broken)
+                {
+                    count = count.checked_add(next[end + 1])?;
+                }
+                current[spring] = count;
+            }
+            next = current;
+        }
+
+        Some(next[0])
+    }
+
+    // Repeat the springs `factor` times, joined by `separator`, and their groups as many times.
+    fn unfold(&self, factor: usize, separator: Condition) -> Self {
         Self {
             springs: self
                 .springs
                 .iter()
                 .copied()
-                .chain(std::iter::once(Condition::Unknown))
+                .chain(std::iter::once(separator))
                 .cycle()
-                .take(4 + self.springs.len() * 5)
-                .collect(),
-            contiguous_groups: self.contiguous_groups.repeat(5),
+                .take((self.springs.len() + 1) * factor)
+                .collect::<Vec<_>>()
+                .split_last()
+                .map(|(_, springs)| springs.to_vec())
+                .unwrap_or_default(),
+            contiguous_groups: self.contiguous_groups.repeat(factor),
         }
     }
 }
@@ -139,24 +329,264 @@ impl FromStr for Row {
     }
 }
 
+// A small xorshift generator, enough to sample arrangements.
+#[derive(Debug, Clone)]
+struct XorShift(u64);
+
+impl XorShift {
+    fn new(seed: u64) -> Self {
+        Self(seed.max(1))
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    // Rejects the values past the last multiple of `n`, so that every result is equally likely.
+    fn below(&mut self, n: u128) -> u128 {
+        let zone = u128::MAX - u128::MAX % n;
+        loop {
+            let value = u128::from(self.next_u64()) << 64 | u128::from(self.next_u64());
+            if value < zone {
+                return value % n;
+            }
+        }
+    }
+}
+
+// A 2-D version of the puzzle: every row and column of the grid has its own groups of broken
+// springs.
+#[derive(Debug, Clone)]
+struct Nonogram {
+    rows: Vec<Vec<usize>>,
+    columns: Vec<Vec<usize>>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Solution {
+    Impossible,
+    Unique(Grid<Condition>),
+    Multiple(Grid<Condition>, Grid<Condition>),
+}
+
+impl Nonogram {
+    fn solve(&self) -> Solution {
+        let (height, width) = (self.rows.len(), self.columns.len());
+        let grid = Grid::new(height, width, vec![Condition::Unknown; height * width])
+            .expect("The grid has the right size.");
+
+        let mut solutions = Vec::new();
+        self.search(grid, &mut solutions);
+
+        let mut solutions = solutions.into_iter();
+        match (solutions.next(), solutions.next()) {
+            (None, _) => Solution::Impossible,
+            (Some(solution), None) => Solution::Unique(solution),
+            (Some(first), Some(second)) => Solution::Multiple(first, second),
+        }
+    }
+
+    // Propagate what can be deduced, then guess the first unknown spring. Stops once two
+    // solutions are found.
+    fn search(&self, mut grid: Grid<Condition>, solutions: &mut Vec<Grid<Condition>>) {
+        if solutions.len() > 1 || !self.propagate(&mut grid) {
+            return;
+        }
+
+        let unknown = (0..grid.height())
+            .flat_map(|idy| (0..grid.width()).map(move |idx| Position::new(idx, idy)))
+            .find(|&position| grid.get(position) == Some(&Condition::Unknown));
+
+        let Some(position) = unknown else {
+            solutions.push(grid);
+            return;
+        };
+
+        for condition in [Condition::Broken, Condition::Operational] {
+            let mut guess = grid.clone();
+            if let Some(spring) = guess.get_mut(position) {
+                *spring = condition;
+            }
+            self.search(guess, solutions);
+        }
+    }
+
+    // Deduce every row and column in turn until nothing changes. Returns `false` if some line
+    // cannot be completed.
+    fn propagate(&self, grid: &mut Grid<Condition>) -> bool {
+        loop {
+            let mut changed = false;
+
+            for (idy, groups) in self.rows.iter().enumerate() {
+                match deduce_line(grid.row_mut(idy), groups) {
+                    Some(line) => changed |= line,
+                    None => return false,
+                }
+            }
+            for (idx, groups) in self.columns.iter().enumerate() {
+                match deduce_line(grid.column_mut(idx), groups) {
+                    Some(line) => changed |= line,
+                    None => return false,
+                }
+            }
+
+            if !changed {
+                return true;
+            }
+        }
+    }
+}
+
+// Deduce a single line of the grid in place, returning whether any spring changed.
+fn deduce_line<'a>(
+    line: impl Iterator<Item = &'a mut Condition>,
+    groups: &[usize],
+) -> Option<bool> {
+    let mut line: Vec<&mut Condition> = line.collect();
+    let row = Row {
+        springs: line.iter().map(|spring| **spring).collect(),
+        contiguous_groups: groups.to_vec(),
+    };
+
+    let mut changed = false;
+    for (spring, condition) in line.iter_mut().zip(row.deduce()?) {
+        if **spring != condition {
+            **spring = condition;
+            changed = true;
+        }
+    }
+
+    Some(changed)
+}
+
+// The groups of each row, then those of each column after an empty line. Lines without broken
+// springs are written `0`.
+impl FromStr for Nonogram {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parse_groups = |section: &str| {
+            section
+                .lines()
+                .map(|line| {
+                    line.split(',')
+                        .map(str::parse)
+                        .filter(|groups| groups != &Ok(0))
+                        .collect::<Result<Vec<usize>, _>>()
+                        .map_err(|e| format!("Invalid groups: {line} ({e})."))
+                })
+                .collect::<Result<Vec<_>, _>>()
+        };
+
+        let (rows, columns) = s
+            .trim()
+            .split_once("\n\n")
+            .ok_or("Missing column groups.")?;
+
+        Ok(Self {
+            rows: parse_groups(rows)?,
+            columns: parse_groups(columns)?,
+        })
+    }
+}
+
+fn render(grid: &Grid<Condition>) -> String {
+    grid.rows()
+        .map(|row| row.copied().map(char::from).collect::<String>())
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
 fn main() -> Result<(), Box<dyn std::error::Error>> {
+    // `nonogram <file>` solves the nonogram described in the file.
+    let mut args = std::env::args().skip(1);
+    let command = args.next();
+    if command.as_deref() == Some("nonogram") {
+        let file = args.next().ok_or("Missing nonogram file.")?;
+        let nonogram: Nonogram = std::fs::read_to_string(file)?.parse()?;
+        match nonogram.solve() {
+            Solution::Impossible => println!("There is no solution."),
+            Solution::Unique(grid) => println!("The only solution is:\n{}", render(&grid)),
+            Solution::Multiple(first, second) => println!(
+                "There are several solutions, including:\n{}\n\nand:\n{}",
+                render(&first),
+                render(&second)
+            ),
+        }
+        return Ok(());
+    }
+
     let input = std::fs::read_to_string(INPUT)?;
     let rows = input
         .lines()
         .map(str::parse)
         .collect::<Result<Vec<Row>, _>>()?;
 
+    // `arrangements <row> <factor>` lists the arrangements of a row unfolded `factor` times
+    // (1 keeping it as it is), while `arrangement <row> <factor> <rank>` and
+    // `sample <row> <factor> [seed]` only show one of them.
+    if let Some(command @ ("arrangements" | "arrangement" | "sample")) = command.as_deref() {
+        let row: usize = args.next().ok_or("Missing row.")?.parse()?;
+        let factor = args.next().ok_or("Missing factor.")?.parse()?;
+        let row = rows
+            .get(row)
+            .ok_or("No such row.")?
+            .unfold(factor, Condition::Unknown);
+        let arrangements: Vec<Vec<Condition>> = match command {
+            "arrangements" => row.arrangements()?.collect(),
+            "arrangement" => {
+                let rank = args.next().ok_or("Missing rank.")?.parse()?;
+                row.nth_arrangement(rank)?.into_iter().collect()
+            }
+            _ => {
+                let seed = args.next().map(|seed| seed.parse()).transpose()?;
+                let mut rng = XorShift::new(seed.unwrap_or(0x2023_1212));
+                row.sample(&mut rng)?.into_iter().collect()
+            }
+        };
+        if arrangements.is_empty() {
+            return Err("No such arrangement.".into());
+        }
+        for arrangement in arrangements {
+            println!(
+                "{}",
+                arrangement.into_iter().map(char::from).collect::<String>()
+            );
+        }
+        return Ok(());
+    }
+
+    // `unfold <factor> [separator]` counts the arrangements with another unfolding.
+    let (factor, separator) = match command.as_deref() {
+        Some("unfold") => {
+            let factor = args.next().ok_or("Missing factor.")?.parse()?;
+            let separator = args.next().unwrap_or("?".to_string());
+            let separator = match separator.as_str() {
+                "." | "#" | "?" => separator.chars().next().map(Condition::from),
+                _ => None,
+            }
+            .ok_or(format!("Invalid separator: {separator}."))?;
+            (factor, separator)
+        }
+        _ => (5, Condition::Unknown),
+    };
+
     println!(
         "The first answer is: {}",
         rows.iter()
             .map(Row::count_possible_arrangements)
-            .sum::<usize>()
+            .sum::<Option<u128>>()
+            .ok_or("Too many arrangements.")?
     );
     println!(
         "The second answer is: {}",
         rows.iter()
-            .map(|row| row.expand().count_possible_arrangements())
-            .sum::<usize>()
+            .map(|row| row.unfold(factor, separator).count_arrangements())
+            .try_fold(0u128, |total, count| total.checked_add(count?))
+            .ok_or("Too many arrangements.")?
     );
 
     Ok(())
@@ -183,11 +613,123 @@ mod tests {
             .collect::<Result<Vec<Row>, _>>()
             .unwrap();
         let actual = rows.iter().map(Row::count_possible_arrangements).sum();
-        let expected: usize = 21;
+        let expected = Some(21);
 
         assert_eq!(expected, actual);
     }
 
+    #[test]
+    fn test_nonogram() {
+        let nonogram: Nonogram = "\
+            1,1\n\
+            5\n\
+            5\n\
+            3\n\
+            1\n\
+            \n\
+            2\n\
+            4\n\
+            4\n\
+            4\n\
+            2\n\
+        "
+        .parse()
+        .unwrap();
+        let Solution::Unique(grid) = nonogram.solve() else {
+            panic!("The heart should have a single solution.");
+        };
+        assert_eq!(".#.#.\n#####\n#####\n.###.\n..#..", render(&grid));
+
+        // The two diagonals.
+        let nonogram: Nonogram = "1\n1\n\n1\n1".parse().unwrap();
+        assert!(matches!(nonogram.solve(), Solution::Multiple(_, _)));
+
+        let nonogram: Nonogram = "2\n0\n\n1\n0".parse().unwrap();
+        assert_eq!(Solution::Impossible, nonogram.solve());
+    }
+
+    #[test]
+    fn test_arrangements() {
+        let row: Row = ".??..??...?##. 1,1,3".parse().unwrap();
+        let arrangements: Vec<String> = row
+            .arrangements()
+            .unwrap()
+            .map(|arrangement| arrangement.into_iter().map(char::from).collect())
+            .collect();
+        let expected = [
+            ".#...#....###.",
+            ".#....#...###.",
+            "..#..#....###.",
+            "..#...#...###.",
+        ];
+        assert_eq!(expected.to_vec(), arrangements);
+
+        // The arrangements of an unfolded row can be reached by rank, without listing them.
+        let row = row.unfold(5, Condition::Unknown);
+        let count = row.count_possible_arrangements().unwrap();
+        assert_eq!(16384, count);
+        let nth = |rank| -> String {
+            let arrangement = row.nth_arrangement(rank).unwrap().unwrap();
+            arrangement.into_iter().map(char::from).collect()
+        };
+        assert_eq!(
+            ".#...#....###.#.#........###.#.#........###.#.#........###.#.#........###.",
+            nth(0)
+        );
+        assert_eq!(
+            "..#...#...###....#...#...###....#...#...###....#...#...###....#...#...###.",
+            nth(count - 1)
+        );
+        assert_eq!(Ok(None), row.nth_arrangement(count));
+
+        let mut rng = XorShift::new(12);
+        for _ in 0..10 {
+            let sample = Row {
+                springs: row.sample(&mut rng).unwrap().unwrap(),
+                contiguous_groups: row.contiguous_groups.clone(),
+            };
+            assert!(sample
+                .springs
+                .iter()
+                .zip(&row.springs)
+                .all(
+                    |(spring, &condition)| condition == Condition::Unknown || *spring == condition
+                ));
+            assert_eq!(Some(1), sample.count_possible_arrangements());
+        }
+    }
+
+    #[test]
+    fn test_unfold() {
+        let rows = EXAMPLE
+            .lines()
+            .map(str::parse)
+            .collect::<Result<Vec<Row>, _>>()
+            .unwrap();
+        for row in &rows {
+            for factor in [1, 2, 5] {
+                let row = row.unfold(factor, Condition::Unknown);
+                assert_eq!(row.count_possible_arrangements(), row.count_arrangements());
+            }
+        }
+
+        let row: Row = "??? 1".parse().unwrap();
+        let unfolded = row.unfold(2, Condition::Operational);
+        let springs: String = unfolded.springs.iter().copied().map(char::from).collect();
+        assert_eq!("???.???", springs);
+        assert_eq!(Some(11), unfolded.count_arrangements());
+
+        // Choosing 20 springs amongst 60, then 50 amongst 150, which does not fit in 128 bits.
+        assert_eq!(
+            Some(4_191_844_505_805_495),
+            row.unfold(20, Condition::Unknown).count_arrangements()
+        );
+        let row = row.unfold(50, Condition::Unknown);
+        assert_eq!(None, row.count_arrangements());
+        assert!(row.nth_arrangement(0).is_err());
+        assert!(row.sample(&mut XorShift::new(12)).is_err());
+    }
+
     #[test]
     fn test_part2() {
         let rows = EXAMPLE
@@ -197,9 +739,12 @@ mod tests {
             .unwrap();
         let actual = rows
             .iter()
-            .map(|row| row.expand().count_possible_arrangements())
+            .map(|row| {
+                row.unfold(5, Condition::Unknown)
+                    .count_possible_arrangements()
+            })
             .sum();
-        let expected: usize = 525152;
+        let expected = Some(525152);
 
         assert_eq!(expected, actual);
     }
@@ -1,5 +1,4 @@
-use advent_of_code_2023::grid::Grid;
-use std::collections::{HashMap, VecDeque};
+use advent_of_code_2023::cycle;
 use std::fmt::Formatter;
 use std::str::FromStr;
 
@@ -25,70 +24,102 @@ impl TryFrom<char> for Rock {
     }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Edge {
+    North,
+    West,
+    South,
+    East,
+}
+
+impl TryFrom<char> for Edge {
+    type Error = String;
+
+    fn try_from(value: char) -> Result<Self, Self::Error> {
+        Ok(match value {
+            'N' => Edge::North,
+            'W' => Edge::West,
+            'S' => Edge::South,
+            'E' => Edge::East,
+            _ => Err(format!("Invalid edge: {value}."))?,
+        })
+    }
+}
+
+const SPIN_CYCLE: [Edge; 4] = [Edge::North, Edge::West, Edge::South, Edge::East];
+
+// Rows are stored as bitboards, with bit `x` standing for column `x`.
+const MAX_WIDTH: usize = u128::BITS as usize;
+
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
-struct Platform(Grid<Rock>);
+struct Platform {
+    width: usize,
+    round: Vec<u128>,
+    cube: Vec<u128>,
+}
 
 impl Platform {
+    fn height(&self) -> usize {
+        self.round.len()
+    }
+
     fn total_load(&self) -> usize {
-        let height = self.0.height();
-        self.0
-            .columns()
-            .flat_map(|column| {
-                column.enumerate().filter_map(|(idx, &rock)| {
-                    if rock == Rock::Round {
-                        Some(height - idx)
-                    } else {
-                        None
-                    }
-                })
+        self.load(Edge::North)
+    }
+
+    // Each round rock contributes its distance from the opposite edge (counting its own row or
+    // column).
+    fn load(&self, edge: Edge) -> usize {
+        let height = self.height();
+        let width = self.width;
+        self.round
+            .iter()
+            .enumerate()
+            .map(|(idy, &row)| match edge {
+                Edge::North => row.count_ones() as usize * (height - idy),
+                Edge::South => row.count_ones() as usize * (idy + 1),
+                Edge::West => bits(row).map(|idx| width - idx).sum(),
+                Edge::East => bits(row).map(|idx| idx + 1).sum(),
             })
             .sum()
     }
 
-    fn tilt_north(&mut self) -> &mut Self {
-        let width = self.0.width();
-
-        for column in 0..width {
-            // Keep track of the empties.
-            let mut empties = VecDeque::new();
-
-            for rock in self.0.column_mut(column) {
-                match rock {
-                    // If an empty spot is available, fill it with our round rock.
-                    Rock::Round => {
-                        if let Some(empty) = empties.pop_front() {
-                            std::mem::swap(rock, empty);
-                            empties.push_back(rock);
-                        }
-                    }
-                    Rock::Cube => empties.clear(),
-                    Rock::Empty => empties.push_back(rock),
-                }
-            }
+    fn tilt(&mut self, edge: Edge) -> &mut Self {
+        match edge {
+            Edge::North => self.tilt_north(),
+            Edge::West => self.tilt_west(),
+            Edge::South => self.tilt_south(),
+            Edge::East => self.tilt_east(),
         }
+    }
 
-        self
+    fn tilt_north(&mut self) -> &mut Self {
+        let rows: Vec<usize> = (0..self.height()).collect();
+        self.settle(&rows)
     }
 
     fn tilt_south(&mut self) -> &mut Self {
-        let width = self.0.width();
-
-        for column in 0..width {
-            // Keep track of the empties.
-            let mut empties = VecDeque::new();
-
-            for rock in self.0.column_mut(column).rev() {
-                match rock {
-                    // If an empty spot is available, fill it with our round rock.
-                    Rock::Round => {
-                        if let Some(empty) = empties.pop_front() {
-                            std::mem::swap(rock, empty);
-                            empties.push_back(rock);
-                        }
-                    }
-                    Rock::Cube => empties.clear(),
-                    Rock::Empty => empties.push_back(rock),
+        let rows: Vec<usize> = (0..self.height()).rev().collect();
+        self.settle(&rows)
+    }
+
+    // Roll the round rocks towards the first of the rows, in a single sweep. Each column keeps
+    // track of where its next rock will stop: right after the last cube-shaped rock or the last
+    // rock which settled.
+    fn settle(&mut self, rows: &[usize]) -> &mut Self {
+        let mut free = vec![0; self.width];
+        for (position, &idy) in rows.iter().enumerate() {
+            for idx in bits(self.cube[idy]) {
+                free[idx] = position + 1;
+            }
+
+            for idx in bits(self.round[idy]) {
+                let target = rows[free[idx]];
+                if target != idy {
+                    self.round[idy] ^= 1 << idx;
+                    self.round[target] |= 1 << idx;
                 }
+                free[idx] += 1;
             }
         }
 
@@ -96,87 +127,116 @@ impl Platform {
     }
 
     fn tilt_west(&mut self) -> &mut Self {
-        let height = self.0.height();
-
-        for row in 0..height {
-            // Keep track of the empties.
-            let mut empties = VecDeque::new();
-
-            for rock in self.0.row_mut(row) {
-                match rock {
-                    // If an empty spot is available, fill it with our round rock.
-                    Rock::Round => {
-                        if let Some(empty) = empties.pop_front() {
-                            std::mem::swap(rock, empty);
-                            empties.push_back(rock);
-                        }
-                    }
-                    Rock::Cube => empties.clear(),
-                    Rock::Empty => empties.push_back(rock),
-                }
-            }
+        let width = self.width;
+        for (round, &cube) in self.round.iter_mut().zip(&self.cube) {
+            *round = segments(cube, width)
+                .filter_map(|(start, end)| {
+                    let count = (*round & low_bits(end) & !low_bits(start)).count_ones() as usize;
+                    (count > 0).then(|| low_bits(count) << start)
+                })
+                .fold(0, |acc, segment| acc | segment);
         }
 
         self
     }
 
     fn tilt_east(&mut self) -> &mut Self {
-        let height = self.0.height();
-
-        for row in 0..height {
-            // Keep track of the empties.
-            let mut empties = VecDeque::new();
-
-            for rock in self.0.row_mut(row).rev() {
-                match rock {
-                    // If an empty spot is available, fill it with our round rock.
-                    Rock::Round => {
-                        if let Some(empty) = empties.pop_front() {
-                            std::mem::swap(rock, empty);
-                            empties.push_back(rock);
-                        }
-                    }
-                    Rock::Cube => empties.clear(),
-                    Rock::Empty => empties.push_back(rock),
-                }
-            }
+        let width = self.width;
+        for (round, &cube) in self.round.iter_mut().zip(&self.cube) {
+            *round = segments(cube, width)
+                .filter_map(|(start, end)| {
+                    let count = (*round & low_bits(end) & !low_bits(start)).count_ones() as usize;
+                    (count > 0).then(|| low_bits(count) << (end - count))
+                })
+                .fold(0, |acc, segment| acc | segment);
         }
 
         self
     }
+}
 
-    fn spin_cycle(&mut self) {
-        self.tilt_north();
-        self.tilt_west();
-        self.tilt_south();
-        self.tilt_east();
-    }
+// The lowest `n` bits set, for `n` up to 128.
+fn low_bits(n: usize) -> u128 {
+    u128::MAX.checked_shr((MAX_WIDTH - n) as u32).unwrap_or(0)
+}
+
+// The indices of the set bits, in increasing order.
+fn bits(mut row: u128) -> impl Iterator<Item = usize> {
+    std::iter::from_fn(move || {
+        (row != 0).then(|| {
+            let idx = row.trailing_zeros() as usize;
+            row &= row - 1;
+            idx
+        })
+    })
+}
+
+// The `start..end` spans of a row between cube-shaped rocks (and the edges).
+fn segments(cube: u128, width: usize) -> impl Iterator<Item = (usize, usize)> {
+    let mut remaining = cube;
+    let mut start = 0;
+    std::iter::from_fn(move || {
+        if start > width {
+            return None;
+        }
+
+        let end = if remaining == 0 {
+            width
+        } else {
+            remaining.trailing_zeros() as usize
+        };
+        remaining &= remaining.wrapping_sub(1);
+
+        let segment = (start, end);
+        start = end + 1;
+        Some(segment)
+    })
 }
 
 impl FromStr for Platform {
     type Err = String;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let height = s.lines().count();
         let width = s.lines().next().unwrap_or_default().len();
-        let rocks = s
-            .lines()
-            .flat_map(|line| line.chars().map(Rock::try_from))
-            .collect::<Result<Vec<Rock>, _>>()?;
+        if width > MAX_WIDTH {
+            return Err(format!("Platform is too wide: {width}."));
+        }
+
+        let mut round = Vec::new();
+        let mut cube = Vec::new();
+        for line in s.lines() {
+            if line.len() != width {
+                return Err(format!("Invalid row length: {line}."));
+            }
+
+            let (mut r, mut c) = (0, 0);
+            for (idx, rock) in line.chars().map(Rock::try_from).enumerate() {
+                match rock? {
+                    Rock::Round => r |= 1 << idx,
+                    Rock::Cube => c |= 1 << idx,
+                    Rock::Empty => (),
+                }
+            }
+
+            round.push(r);
+            cube.push(c);
+        }
 
-        Ok(Self(Grid::new(height, width, rocks).unwrap()))
+        Ok(Self { width, round, cube })
     }
 }
 
 impl std::fmt::Display for Platform {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         let mut s = String::new();
-        for row in self.0.rows() {
-            for rock in row {
-                s.push(match rock {
-                    Rock::Round => 'O',
-                    Rock::Cube => '#',
-                    Rock::Empty => '.',
+        for (round, cube) in self.round.iter().zip(&self.cube) {
+            for idx in 0..self.width {
+                s.push(if round & (1 << idx) != 0 {
+                    'O'
+                } else if cube & (1 << idx) != 0 {
+                    '#'
+                } else {
+                    '.'
                 });
             }
 
@@ -187,28 +247,61 @@ impl std::fmt::Display for Platform {
     }
 }
 
-fn part2(mut platform: Platform) -> usize {
-    let mut h = HashMap::new();
-    const CYCLES: usize = 1000000000;
+// A sequence of tilts, repeated a number of times (e.g. "NWSE×1000000000", "N" or "EENS").
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Tilts {
+    sequence: Vec<Edge>,
+    repetitions: usize,
+}
 
-    if let Some((current, previous)) =
-        (0..=CYCLES).find_map(|cycle| match h.insert(platform.clone(), cycle) {
-            Some(previous) => Some((cycle, previous)),
-            None => {
-                platform.spin_cycle();
-                None
+impl Tilts {
+    fn apply(&self, platform: &Platform) -> Platform {
+        let step = |platform: &mut Platform| {
+            for &edge in &self.sequence {
+                platform.tilt(edge);
             }
+        };
+
+        cycle::state_after(platform, step, self.repetitions)
+    }
+}
+
+impl FromStr for Tilts {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (sequence, repetitions) = match s.trim().split_once(['×', 'x', '*']) {
+            Some((sequence, repetitions)) => (
+                sequence,
+                repetitions
+                    .trim()
+                    .parse()
+                    .map_err(|e| format!("Invalid repetitions: {repetitions} ({e})."))?,
+            ),
+            None => (s.trim(), 1),
+        };
+
+        let sequence = sequence
+            .trim()
+            .chars()
+            .map(Edge::try_from)
+            .collect::<Result<Vec<Edge>, _>>()?;
+
+        Ok(Self {
+            sequence,
+            repetitions,
         })
-    {
-        // If we have found a recurring pattern, we can skip ahead.
-        let period_length = current - previous;
-        let remainder = (CYCLES - current) % period_length;
-        for _ in 0..remainder {
-            platform.spin_cycle();
-        }
+    }
+}
+
+fn part2(platform: Platform) -> usize {
+    const CYCLES: usize = 1000000000;
+    let tilts = Tilts {
+        sequence: SPIN_CYCLE.to_vec(),
+        repetitions: CYCLES,
     };
 
-    platform.total_load()
+    tilts.apply(&platform).total_load()
 }
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
@@ -250,6 +343,119 @@ mod tests {
         assert_eq!(expected, actual);
     }
 
+    #[test]
+    fn test_spin_cycle() {
+        let platform = EXAMPLE.parse::<Platform>().unwrap();
+        let actual = "NWSE"
+            .parse::<Tilts>()
+            .unwrap()
+            .apply(&platform)
+            .to_string();
+        let expected = "\
+            .....#....\n\
+            ....#...O#\n\
+            ...OO##...\n\
+            .OO#......\n\
+            .....OOO#.\n\
+            .O#...O#.#\n\
+            ....O#....\n\
+            ......OOOO\n\
+            #...O###..\n\
+            #..OO#....\n\
+        ";
+
+        assert_eq!(expected, actual);
+    }
+
+    #[test]
+    fn test_tilts() {
+        let platform = EXAMPLE.parse::<Platform>().unwrap();
+        let actual = "N".parse::<Tilts>().unwrap().apply(&platform).total_load();
+        assert_eq!(136, actual);
+
+        // Tilting south is tilting north, upside down.
+        let flipped = EXAMPLE
+            .lines()
+            .rev()
+            .map(|line| format!("{line}\n"))
+            .collect::<String>()
+            .parse::<Platform>()
+            .unwrap();
+        let tilts = "EWS".parse::<Tilts>().unwrap();
+        let flipped_tilts = "EWN".parse::<Tilts>().unwrap();
+        assert_eq!(
+            flipped_tilts.apply(&flipped).load(Edge::North),
+            tilts.apply(&platform).load(Edge::South)
+        );
+
+        let spin_cycles = "NWSE×1000000000".parse::<Tilts>().unwrap();
+        assert_eq!(64, spin_cycles.apply(&platform).total_load());
+
+        assert!("NWQ".parse::<Tilts>().is_err());
+    }
+
+    // The previous implementation, moving rocks one row at a time until none can move.
+    fn shift_until_stable(platform: &mut Platform, edge: Edge) {
+        let height = platform.height();
+        let (from, to): (Vec<usize>, Vec<usize>) = match edge {
+            Edge::North => ((1..height).rev().collect(), (0..height - 1).rev().collect()),
+            Edge::South => ((0..height - 1).collect(), (1..height).collect()),
+            _ => unreachable!(),
+        };
+
+        loop {
+            let mut moved = false;
+            for (&from, &to) in from.iter().zip(&to) {
+                let rolling = platform.round[from] & !(platform.round[to] | platform.cube[to]);
+                if rolling != 0 {
+                    platform.round[from] ^= rolling;
+                    platform.round[to] |= rolling;
+                    moved = true;
+                }
+            }
+
+            if !moved {
+                return;
+            }
+        }
+    }
+
+    #[test]
+    fn test_settle() {
+        // A tall platform with long columns of rocks, from a fixed pseudo-random sequence.
+        let mut seed: u64 = 14;
+        let mut random = || {
+            seed = seed
+                .wrapping_mul(6364136223846793005)
+                .wrapping_add(1442695040888963407);
+            seed >> 33
+        };
+        let tall = (0..500)
+            .map(|_| {
+                (0..100)
+                    .map(|_| match random() % 20 {
+                        0 => '#',
+                        1..=8 => 'O',
+                        _ => '.',
+                    })
+                    .chain(['\n'])
+                    .collect::<String>()
+            })
+            .collect::<String>();
+
+        for input in [EXAMPLE, &tall] {
+            let platform = input.parse::<Platform>().unwrap();
+            for edge in [Edge::North, Edge::South] {
+                let mut expected = platform.clone();
+                shift_until_stable(&mut expected, edge);
+                let mut actual = platform.clone();
+                actual.tilt(edge);
+
+                assert_eq!(expected, actual);
+            }
+        }
+    }
+
     #[test]
     fn test_part2() {
         let platform = EXAMPLE.parse::<Platform>().unwrap();
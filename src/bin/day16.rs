@@ -28,50 +28,120 @@ impl TryFrom<char> for Tile {
     }
 }
 
+impl Tile {
+    // The direction in which a ray leaves the tile, and the second one if it is split.
+    fn deflect(self, direction: Direction) -> (Direction, Option<Direction>) {
+        match (self, direction) {
+            (Tile::VerticalSplitter, Direction::Right | Direction::Left) => {
+                (Direction::Down, Some(Direction::Up))
+            }
+            (Tile::HorizontalSplitter, Direction::Up | Direction::Down) => {
+                (Direction::Right, Some(Direction::Left))
+            }
+            (Tile::UpRightMirror, Direction::Up) => (Direction::Right, None),
+            (Tile::UpRightMirror, Direction::Down) => (Direction::Left, None),
+            (Tile::UpRightMirror, Direction::Right) => (Direction::Up, None),
+            (Tile::UpRightMirror, Direction::Left) => (Direction::Down, None),
+            (Tile::DownRightMirror, Direction::Up) => (Direction::Left, None),
+            (Tile::DownRightMirror, Direction::Down) => (Direction::Right, None),
+            (Tile::DownRightMirror, Direction::Right) => (Direction::Down, None),
+            (Tile::DownRightMirror, Direction::Left) => (Direction::Up, None),
+            _ => (direction, None),
+        }
+    }
+}
+
+impl From<Tile> for char {
+    fn from(value: Tile) -> Self {
+        match value {
+            Tile::Empty => '.',
+            Tile::UpRightMirror => '/',
+            Tile::DownRightMirror => '\\',
+            Tile::VerticalSplitter => '|',
+            Tile::HorizontalSplitter => '-',
+        }
+    }
+}
+
+// Every (position, direction) state the beam goes through.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Beam<'a> {
+    contraption: &'a Contraption,
+    states: HashSet<(Position, Direction)>,
+}
+
+impl Beam<'_> {
+    fn energized(&self) -> usize {
+        self.states
+            .iter()
+            .map(|&(position, _)| position)
+            .collect::<HashSet<Position>>()
+            .len()
+    }
+}
+
+// Draw the beam over the contraption, as in the puzzle: arrows for tiles crossed in a single
+// direction, the number of directions otherwise. Mirrors and splitters are left as is.
+impl std::fmt::Display for Beam<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut tiles: HashMap<Position, Vec<Direction>> = HashMap::new();
+        for &(position, direction) in &self.states {
+            tiles.entry(position).or_default().push(direction);
+        }
+
+        let mut s = String::new();
+        for (idy, row) in self.contraption.0.rows().enumerate() {
+            for (idx, &tile) in row.enumerate() {
+                let directions = tiles.get(&Position::new(idx, idy));
+                s.push(match (tile, directions) {
+                    (Tile::Empty, Some(directions)) if directions.len() == 1 => {
+                        match directions.first() {
+                            Some(Direction::Up) => '^',
+                            Some(Direction::Down) => 'v',
+                            Some(Direction::Left) => '<',
+                            _ => '>',
+                        }
+                    }
+                    (Tile::Empty, Some(directions)) => {
+                        char::from_digit(directions.len() as u32, 10).unwrap_or('*')
+                    }
+                    _ => char::from(tile),
+                });
+            }
+
+            s.push('\n');
+        }
+
+        write!(f, "{s}")
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 struct Contraption(Grid<Tile>);
 
 impl Contraption {
-    fn energize(&self, initial_position: Position, initial_direction: Direction) -> usize {
+    fn energize(&self, initial_position: Position, initial_direction: Direction) -> Beam<'_> {
         // Keep track of the energized tiles. By keeping track of the direction of the ray, we
         // can avoid infinite loops.
-        let mut energized_tiles: HashMap<Position, HashSet<Direction>> = HashMap::new();
+        let mut beam = Beam {
+            contraption: self,
+            states: HashSet::new(),
+        };
         let mut rays = vec![(initial_position, initial_direction)];
 
-        while let Some((position, mut direction)) = rays.pop() {
-            if let Some(tile) = self.0.get(position) {
-                if !energized_tiles
-                    .entry(position)
-                    .or_default()
-                    .insert(direction)
-                {
+        while let Some((position, direction)) = rays.pop() {
+            if let Some(&tile) = self.0.get(position) {
+                if !beam.states.insert((position, direction)) {
                     continue;
                 }
 
                 // Modify the direction if encountering mirrors. For the splitters, we manually
                 // push a second direction.
-                match (tile, direction) {
-                    (Tile::VerticalSplitter, Direction::Right | Direction::Left) => {
-                        if let Some(p) = position + Direction::Up {
-                            rays.push((p, Direction::Up));
-                        }
-                        direction = Direction::Down;
-                    }
-                    (Tile::HorizontalSplitter, Direction::Up | Direction::Down) => {
-                        if let Some(p) = position + Direction::Left {
-                            rays.push((p, Direction::Left));
-                        }
-                        direction = Direction::Right;
+                let (direction, split) = tile.deflect(direction);
+                if let Some(split) = split {
+                    if let Some(p) = position + split {
+                        rays.push((p, split));
                     }
-                    (Tile::UpRightMirror, Direction::Up) => direction = Direction::Right,
-                    (Tile::UpRightMirror, Direction::Down) => direction = Direction::Left,
-                    (Tile::UpRightMirror, Direction::Right) => direction = Direction::Up,
-                    (Tile::UpRightMirror, Direction::Left) => direction = Direction::Down,
-                    (Tile::DownRightMirror, Direction::Up) => direction = Direction::Left,
-                    (Tile::DownRightMirror, Direction::Down) => direction = Direction::Right,
-                    (Tile::DownRightMirror, Direction::Right) => direction = Direction::Down,
-                    (Tile::DownRightMirror, Direction::Left) => direction = Direction::Up,
-                    _ => (),
                 }
 
                 if let Some(p) = position + direction {
@@ -80,7 +150,220 @@ impl Contraption {
             }
         }
 
-        energized_tiles.len()
+        beam
+    }
+
+    fn index(&self, position: Position) -> usize {
+        position.x() + position.y() * self.0.width()
+    }
+
+    // Follow a single ray until it is split (returning the splitter), leaves the contraption or
+    // loops back on itself.
+    fn follow(
+        &self,
+        mut position: Position,
+        mut direction: Direction,
+    ) -> (TileSet, Option<Position>) {
+        let mut tiles = TileSet::new(self.0.width() * self.0.height());
+        let mut seen = HashSet::new();
+
+        while let Some(&tile) = self.0.get(position) {
+            if !seen.insert((position, direction)) {
+                break;
+            }
+
+            let (next, split) = tile.deflect(direction);
+            if split.is_some() {
+                return (tiles, Some(position));
+            }
+            direction = next;
+
+            tiles.insert(self.index(position));
+            match position + direction {
+                Some(p) => position = p,
+                None => break,
+            }
+        }
+
+        (tiles, None)
+    }
+}
+
+// Tiles as a bitset, indexed by `x + y * width`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct TileSet(Vec<u64>);
+
+impl TileSet {
+    fn new(size: usize) -> Self {
+        TileSet(vec![0; size.div_ceil(64)])
+    }
+
+    fn insert(&mut self, idx: usize) {
+        self.0[idx / 64] |= 1 << (idx % 64);
+    }
+
+    fn union_with(&mut self, other: &Self) {
+        for (a, b) in self.0.iter_mut().zip(&other.0) {
+            *a |= b;
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.0.iter().map(|word| word.count_ones() as usize).sum()
+    }
+}
+
+// Rays only branch at splitters, so the contraption boils down to a graph whose nodes are the
+// splitters, each with the tiles energized until the next splitters. Once the strongly connected
+// components are collapsed, we know everything energized from any splitter.
+#[derive(Debug, Clone)]
+struct BeamGraph<'a> {
+    contraption: &'a Contraption,
+    splitters: HashMap<Position, usize>,
+    components: Vec<usize>,
+    reach: Vec<TileSet>,
+}
+
+impl<'a> BeamGraph<'a> {
+    fn new(contraption: &'a Contraption) -> Self {
+        let width = contraption.0.width();
+        let positions: Vec<Position> = contraption
+            .0
+            .rows()
+            .enumerate()
+            .flat_map(|(idy, row)| {
+                row.enumerate().filter_map(move |(idx, tile)| {
+                    matches!(tile, Tile::VerticalSplitter | Tile::HorizontalSplitter)
+                        .then_some(Position::new(idx, idy))
+                })
+            })
+            .collect();
+        let splitters: HashMap<Position, usize> = positions
+            .iter()
+            .enumerate()
+            .map(|(idx, &position)| (position, idx))
+            .collect();
+
+        let mut local = Vec::with_capacity(positions.len());
+        let mut edges = Vec::with_capacity(positions.len());
+        for &position in &positions {
+            let mut tiles = TileSet::new(width * contraption.0.height());
+            tiles.insert(contraption.index(position));
+            let mut next = Vec::new();
+
+            let directions = match contraption.0.get(position) {
+                Some(Tile::VerticalSplitter) => [Direction::Up, Direction::Down],
+                _ => [Direction::Left, Direction::Right],
+            };
+            for direction in directions {
+                if let Some(p) = position + direction {
+                    let (segment, splitter) = contraption.follow(p, direction);
+                    tiles.union_with(&segment);
+                    next.extend(splitter.map(|splitter| splitters[&splitter]));
+                }
+            }
+
+            local.push(tiles);
+            edges.push(next);
+        }
+
+        let mut tarjan = Tarjan {
+            edges: &edges,
+            local: &local,
+            counter: 0,
+            indices: vec![None; positions.len()],
+            lowlinks: vec![0; positions.len()],
+            stack: Vec::new(),
+            on_stack: vec![false; positions.len()],
+            components: vec![0; positions.len()],
+            reach: Vec::new(),
+        };
+        for node in 0..positions.len() {
+            if tarjan.indices[node].is_none() {
+                tarjan.visit(node);
+            }
+        }
+
+        BeamGraph {
+            contraption,
+            splitters,
+            components: tarjan.components,
+            reach: tarjan.reach,
+        }
+    }
+
+    fn energized(&self, position: Position, direction: Direction) -> usize {
+        let (mut tiles, splitter) = self.contraption.follow(position, direction);
+        if let Some(splitter) = splitter {
+            tiles.union_with(&self.reach[self.components[self.splitters[&splitter]]]);
+        }
+
+        tiles.len()
+    }
+}
+
+// Tarjan's algorithm finds the components in reverse topological order, so everything reachable
+// from a component is known by the time it is complete.
+struct Tarjan<'g> {
+    edges: &'g [Vec<usize>],
+    local: &'g [TileSet],
+    counter: usize,
+    indices: Vec<Option<usize>>,
+    lowlinks: Vec<usize>,
+    stack: Vec<usize>,
+    on_stack: Vec<bool>,
+    components: Vec<usize>,
+    reach: Vec<TileSet>,
+}
+
+impl Tarjan<'_> {
+    fn visit(&mut self, node: usize) {
+        let index = self.counter;
+        self.counter += 1;
+        self.indices[node] = Some(index);
+        self.lowlinks[node] = index;
+        self.stack.push(node);
+        self.on_stack[node] = true;
+
+        for &next in &self.edges[node] {
+            match self.indices[next] {
+                None => {
+                    self.visit(next);
+                    self.lowlinks[node] = self.lowlinks[node].min(self.lowlinks[next]);
+                }
+                Some(idx) if self.on_stack[next] => {
+                    self.lowlinks[node] = self.lowlinks[node].min(idx);
+                }
+                _ => (),
+            }
+        }
+
+        if self.lowlinks[node] != index {
+            return;
+        }
+
+        let component = self.reach.len();
+        let mut members = Vec::new();
+        while let Some(member) = self.stack.pop() {
+            self.on_stack[member] = false;
+            self.components[member] = component;
+            members.push(member);
+            if member == node {
+                break;
+            }
+        }
+
+        let mut reach = self.local[node].clone();
+        for &member in &members {
+            reach.union_with(&self.local[member]);
+            for &next in &self.edges[member] {
+                if self.components[next] != component {
+                    reach.union_with(&self.reach[self.components[next]]);
+                }
+            }
+        }
+
+        self.reach.push(reach);
     }
 }
 
@@ -100,10 +383,13 @@ impl FromStr for Contraption {
 }
 
 fn part1(contraption: &Contraption) -> usize {
-    contraption.energize(Position::default(), Direction::Right)
+    contraption
+        .energize(Position::default(), Direction::Right)
+        .energized()
 }
 
 fn part2(contraption: &Contraption) -> usize {
+    let graph = BeamGraph::new(contraption);
     let height = contraption.0.height();
     let width = contraption.0.width();
 
@@ -116,7 +402,7 @@ fn part2(contraption: &Contraption) -> usize {
         .chain(bottom_row)
         .chain(left_column)
         .chain(right_column)
-        .map(|(p, d)| contraption.energize(p, d))
+        .map(|(p, d)| graph.energized(p, d))
         .max()
         .unwrap_or_default()
 }
@@ -157,6 +443,46 @@ mod tests {
         assert_eq!(expected, actual);
     }
 
+    #[test]
+    fn test_render() {
+        let contraption = EXAMPLE.parse::<Contraption>().unwrap();
+        let beam = contraption.energize(Position::default(), Direction::Right);
+        let actual = beam.to_string();
+        let expected = "\
+            >|<<<\\....\n\
+            |v-.\\^....\n\
+            .v...|->>>\n\
+            .v...v^.|.\n\
+            .v...v^...\n\
+            .v...v^..\\\n\
+            .v../2\\\\..\n\
+            <->-/vv|..\n\
+            .|<<<2-|.\\\n\
+            .v//.|.v..\n\
+        ";
+
+        assert_eq!(expected, actual);
+        assert_eq!(46, beam.energized());
+    }
+
+    #[test]
+    fn test_beam_graph() {
+        let contraption = EXAMPLE.parse::<Contraption>().unwrap();
+        let graph = BeamGraph::new(&contraption);
+
+        for idx in 0..10 {
+            for (position, direction) in [
+                (Position::new(idx, 0), Direction::Down),
+                (Position::new(idx, 9), Direction::Up),
+                (Position::new(0, idx), Direction::Right),
+                (Position::new(9, idx), Direction::Left),
+            ] {
+                let expected = contraption.energize(position, direction).energized();
+                assert_eq!(expected, graph.energized(position, direction));
+            }
+        }
+    }
+
     #[test]
     fn test_part2() {
         let contraption = EXAMPLE.parse::<Contraption>().unwrap();
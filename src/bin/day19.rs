@@ -1,88 +1,264 @@
 use advent_of_code_2023::range_extension::RangeExtension;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::ops::Range;
-use std::str::FromStr;
 
 const INPUT: &str = "./input/day19.txt";
 
-#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
-struct Part {
-    x: u64,
-    m: u64,
-    a: u64,
-    s: u64,
+// Each rating is between 1 and 4000.
+const RATINGS: Range<u64> = 1..4001;
+
+// The index of a category, in the order declared by the parts (e.g. x, m, a, s).
+type Category = usize;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Operator {
+    Less,
+    LessOrEqual,
+    Greater,
+    GreaterOrEqual,
+    Equal,
+    NotEqual,
 }
 
-impl Part {
-    fn rating(self) -> u64 {
-        self.x + self.m + self.a + self.s
-    }
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Token<'token> {
+    Name(&'token str),
+    Number(u64),
+    Operator(Operator),
+    Assign,
+    OpenBrace,
+    CloseBrace,
+    Comma,
+    Colon,
 }
 
-impl FromStr for Part {
-    type Err = String;
-
-    fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let mut part = Self::default();
-        let iter = s.trim_matches(|c| c == '{' || c == '}').split(',');
-
-        for rating in iter {
-            if let Some((name, value)) = rating.split_once('=') {
-                let value = value.parse().unwrap();
-                match name {
-                    "x" => part.x = value,
-                    "m" => part.m = value,
-                    "a" => part.a = value,
-                    "s" => part.s = value,
-                    _ => (),
+// Split a line into tokens, each with its (1-based) column, counted in characters.
+fn tokenize(line: &str) -> Result<Vec<(usize, Token<'_>)>, String> {
+    let mut tokens = Vec::new();
+    let mut chars = line.char_indices().enumerate().peekable();
+
+    while let Some((column, (idx, c))) = chars.next() {
+        let column = column + 1;
+        let token = match c {
+            c if c.is_whitespace() => continue,
+            c if c.is_ascii_alphanumeric() || c == '_' => {
+                let mut end = idx + c.len_utf8();
+                while let Some(&(_, (next, c))) = chars.peek() {
+                    if !(c.is_ascii_alphanumeric() || c == '_') {
+                        break;
+                    }
+                    end = next + c.len_utf8();
+                    chars.next();
+                }
+
+                let word = &line[idx..end];
+                if word.bytes().all(|b| b.is_ascii_digit()) {
+                    Token::Number(
+                        word.parse()
+                            .map_err(|e| format!("column {column}: {word} ({e})"))?,
+                    )
+                } else {
+                    Token::Name(word)
                 }
             }
+            '<' | '>' | '=' | '!' => {
+                let followed_by_equal = chars.next_if(|&(_, (_, c))| c == '=').is_some();
+                match (c, followed_by_equal) {
+                    ('<', false) => Token::Operator(Operator::Less),
+                    ('<', true) => Token::Operator(Operator::LessOrEqual),
+                    ('>', false) => Token::Operator(Operator::Greater),
+                    ('>', true) => Token::Operator(Operator::GreaterOrEqual),
+                    ('=', false) => Token::Assign,
+                    ('=', true) => Token::Operator(Operator::Equal),
+                    ('!', true) => Token::Operator(Operator::NotEqual),
+                    _ => Err(format!("column {column}: expected '!='"))?,
+                }
+            }
+            '{' => Token::OpenBrace,
+            '}' => Token::CloseBrace,
+            ',' => Token::Comma,
+            ':' => Token::Colon,
+            _ => Err(format!("column {column}: unexpected character '{c}'"))?,
+        };
+
+        tokens.push((column, token));
+    }
+
+    Ok(tokens)
+}
+
+// Walks through the tokens of a single line.
+struct Parser<'token> {
+    tokens: std::vec::IntoIter<(usize, Token<'token>)>,
+    end: usize,
+}
+
+impl<'token> Parser<'token> {
+    fn new(line: &'token str) -> Result<Self, String> {
+        Ok(Parser {
+            tokens: tokenize(line)?.into_iter(),
+            end: line.chars().count() + 1,
+        })
+    }
+
+    fn next(&mut self, expected: &str) -> Result<(usize, Token<'token>), String> {
+        self.tokens
+            .next()
+            .ok_or_else(|| format!("column {}: expected {expected}", self.end))
+    }
+
+    fn expect(&mut self, token: Token, expected: &str) -> Result<(), String> {
+        match self.next(expected)? {
+            (_, t) if t == token => Ok(()),
+            (column, t) => Err(format!("column {column}: expected {expected}, found {t:?}")),
+        }
+    }
+
+    fn name(&mut self) -> Result<(usize, &'token str), String> {
+        match self.next("a name")? {
+            (column, Token::Name(name)) => Ok((column, name)),
+            (column, t) => Err(format!("column {column}: expected a name, found {t:?}")),
+        }
+    }
+
+    fn number(&mut self) -> Result<u64, String> {
+        match self.next("a number")? {
+            (_, Token::Number(value)) => Ok(value),
+            (column, t) => Err(format!("column {column}: expected a number, found {t:?}")),
         }
+    }
 
-        Ok(part)
+    fn finish(&mut self) -> Result<(), String> {
+        match self.tokens.next() {
+            None => Ok(()),
+            Some((column, t)) => Err(format!("column {column}: unexpected {t:?}")),
+        }
     }
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
-struct PartRange {
-    x: Range<u64>,
-    m: Range<u64>,
-    a: Range<u64>,
-    s: Range<u64>,
-}
+struct Part(Vec<u64>);
 
-impl PartRange {
-    fn is_empty(&self) -> bool {
-        self.x.start == self.x.end
-            || self.m.start == self.m.end
-            || self.a.start == self.a.end
-            || self.s.start == self.s.end
+impl Part {
+    fn rating(&self) -> u64 {
+        self.0.iter().sum()
     }
-    fn possible_combinations(&self) -> u64 {
-        (self.x.end - self.x.start)
-            * (self.m.end - self.m.start)
-            * (self.a.end - self.a.start)
-            * (self.s.end - self.s.start)
+
+    fn get(&self, category: Category) -> u64 {
+        self.0[category]
+    }
+}
+
+// The parts, as "{x=787,m=2655,a=1222,s=2876}". The first one declares the category names, which
+// the others must then all use.
+fn parse_parts(s: &str) -> Result<(Vec<&str>, Vec<Part>), String> {
+    let mut categories = Vec::new();
+    let mut parts = Vec::new();
+
+    for (idx, line) in s.lines().enumerate() {
+        let part = parse_part(line, &mut categories, idx == 0)
+            .map_err(|e| format!("Part {}, {e}.", idx + 1))?;
+        parts.push(part);
     }
+
+    Ok((categories, parts))
 }
 
-impl Default for PartRange {
-    fn default() -> Self {
-        Self {
-            x: 1..4001,
-            m: 1..4001,
-            a: 1..4001,
-            s: 1..4001,
+fn parse_part<'p>(
+    line: &'p str,
+    categories: &mut Vec<&'p str>,
+    declare: bool,
+) -> Result<Part, String> {
+    let mut parser = Parser::new(line)?;
+    let mut ratings = Vec::new();
+    parser.expect(Token::OpenBrace, "'{'")?;
+    loop {
+        let (column, name) = parser.name()?;
+        parser.expect(Token::Assign, "'='")?;
+        let value = parser.number()?;
+        if !RATINGS.contains(&value) {
+            return Err(format!("column {column}: rating {value} is out of range"));
+        }
+        if ratings.iter().any(|&(_, n, _)| n == name) {
+            return Err(format!("column {column}: duplicate category '{name}'"));
+        }
+        ratings.push((column, name, value));
+
+        match parser.next("',' or '}'")? {
+            (_, Token::Comma) => (),
+            (_, Token::CloseBrace) => break,
+            (column, t) => Err(format!(
+                "column {column}: expected ',' or '}}', found {t:?}"
+            ))?,
         }
     }
+    parser.finish()?;
+
+    if declare {
+        *categories = ratings.iter().map(|&(_, name, _)| name).collect();
+    } else if ratings.len() != categories.len() {
+        return Err(format!("expected {} categories", categories.len()));
+    }
+
+    let mut part = Part(vec![0; categories.len()]);
+    for &(column, name, value) in &ratings {
+        let category = categories
+            .iter()
+            .position(|&c| c == name)
+            .ok_or_else(|| format!("column {column}: unknown category '{name}'"))?;
+        part.0[category] = value;
+    }
+
+    Ok(part)
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
-enum Category {
-    X,
-    M,
-    A,
-    S,
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct PartRange(Vec<Range<u64>>);
+
+impl PartRange {
+    fn new(categories: usize) -> Self {
+        PartRange(vec![RATINGS; categories])
+    }
+
+    fn get(&self, category: Category) -> &Range<u64> {
+        &self.0[category]
+    }
+
+    fn with(&self, category: Category, range: Range<u64>) -> Self {
+        let mut part_range = self.clone();
+        part_range.0[category] = range;
+        part_range
+    }
+
+    // Split along a category, into the parts inside the range (if any) and outside of it.
+    fn split(&self, category: Category, range: &Range<u64>) -> (Option<Self>, Vec<Self>) {
+        let current = self.get(category);
+        let inside = range.intersection(current);
+        if inside.is_empty() {
+            return (None, vec![self.clone()]);
+        }
+
+        let outside = [current.start..inside.start, inside.end..current.end]
+            .into_iter()
+            .filter(|range| !range.is_empty())
+            .map(|range| self.with(category, range))
+            .collect();
+
+        (Some(self.with(category, inside)), outside)
+    }
+
+    fn contains(&self, part: &Part) -> bool {
+        part.0.len() == self.0.len()
+            && self
+                .0
+                .iter()
+                .zip(&part.0)
+                .all(|(range, value)| range.contains(value))
+    }
+
+    fn possible_combinations(&self) -> u64 {
+        self.0.iter().map(|range| range.end - range.start).product()
+    }
 }
 
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
@@ -92,8 +268,28 @@ enum Outcome<'outcome> {
     Workflow(&'outcome str),
 }
 
+impl<'outcome> Outcome<'outcome> {
+    fn new(name: &'outcome str) -> Self {
+        match name {
+            "A" => Outcome::Accept,
+            "R" => Outcome::Reject,
+            workflow => Outcome::Workflow(workflow),
+        }
+    }
+
+    fn name(&self) -> &'outcome str {
+        match self {
+            Outcome::Accept => "A",
+            Outcome::Reject => "R",
+            Outcome::Workflow(workflow) => workflow,
+        }
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 struct Rule<'rule> {
+    // Where the rule starts, for diagnostics.
+    column: usize,
     category: Category,
     range: Range<u64>,
     outcome: Outcome<'rule>,
@@ -105,192 +301,534 @@ enum Condition<'condition> {
     None(Outcome<'condition>),
 }
 
-impl<'rule> Condition<'rule> {
-    fn new(s: &'rule str) -> Self {
-        if let Some((condition, outcome)) = s.split_once(':') {
-            let category = match &condition[0..1] {
-                "x" => Category::X,
-                "m" => Category::M,
-                "a" => Category::A,
-                "s" => Category::S,
-                _ => unreachable!(),
-            };
-            let value = u64::from_str(&condition[2..]).unwrap();
-            let range = match &condition[1..2] {
-                "<" => 0..value,
-                ">" => value + 1..u64::MAX,
-                _ => unreachable!(),
-            };
-            let outcome = match outcome {
-                "A" => Outcome::Accept,
-                "R" => Outcome::Reject,
-                workflow => Outcome::Workflow(workflow),
-            };
-            Self::Condition(Rule {
-                category,
-                range,
-                outcome,
-            })
-        } else {
-            let outcome = match s {
-                "A" => Outcome::Accept,
-                "R" => Outcome::Reject,
-                workflow => Outcome::Workflow(workflow),
-            };
-            Self::None(outcome)
+#[derive(Default, Debug)]
+#[allow(clippy::type_complexity)]
+struct Workflows<'workflows>(HashMap<&'workflows str, Vec<Condition<'workflows>>>);
+
+impl<'workflows> Workflows<'workflows> {
+    // Workflows such as "px{a<2006:qkq,m>=2090:A,rfg}", with the comparisons against the
+    // categories declared by the parts.
+    fn new(s: &'workflows str, categories: &[&str]) -> Result<Self, String> {
+        let mut h = HashMap::new();
+        for (idx, line) in s.lines().enumerate() {
+            let (name, conditions) = Self::parse_workflow(line, categories)
+                .map_err(|e| format!("Line {}, {e}.", idx + 1))?;
+            if h.insert(name, conditions).is_some() {
+                return Err(format!("Line {}, duplicate workflow '{name}'.", idx + 1));
+            }
         }
-    }
 
-    fn apply(&self, part: Part) -> Option<&Outcome<'rule>> {
-        match self {
-            Condition::Condition(rule) => {
-                let test = match rule.category {
-                    Category::X => part.x,
-                    Category::M => part.m,
-                    Category::A => part.a,
-                    Category::S => part.s,
-                };
+        Ok(Self(h))
+    }
 
-                if rule.range.contains(&test) {
-                    Some(&rule.outcome)
-                } else {
-                    None
+    fn parse_workflow(
+        line: &'workflows str,
+        categories: &[&str],
+    ) -> Result<(&'workflows str, Vec<Condition<'workflows>>), String> {
+        let mut parser = Parser::new(line)?;
+        let (_, name) = parser.name()?;
+        parser.expect(Token::OpenBrace, "'{'")?;
+
+        let mut conditions = Vec::new();
+        loop {
+            let (column, first) = parser.name()?;
+            match parser.next("an operator, ',' or '}'")? {
+                (_, Token::CloseBrace) => {
+                    conditions.push(Condition::None(Outcome::new(first)));
+                    break;
+                }
+                (_, Token::Operator(operator)) => {
+                    let category = categories
+                        .iter()
+                        .position(|&c| c == first)
+                        .ok_or_else(|| format!("column {column}: unknown category '{first}'"))?;
+                    let value = parser.number()?;
+                    parser.expect(Token::Colon, "':'")?;
+                    let (_, outcome) = parser.name()?;
+                    let outcome = Outcome::new(outcome);
+
+                    // Not equal is the same as either less or greater.
+                    let (range, other) = match operator {
+                        Operator::Less => (0..value, None),
+                        Operator::LessOrEqual => (0..value.saturating_add(1), None),
+                        Operator::Greater => (value.saturating_add(1)..u64::MAX, None),
+                        Operator::GreaterOrEqual => (value..u64::MAX, None),
+                        Operator::Equal => (value..value.saturating_add(1), None),
+                        Operator::NotEqual => (0..value, Some(value.saturating_add(1)..u64::MAX)),
+                    };
+                    conditions.extend(std::iter::once(range).chain(other).map(|range| {
+                        Condition::Condition(Rule {
+                            column,
+                            category,
+                            range,
+                            outcome: outcome.clone(),
+                        })
+                    }));
+
+                    parser.expect(Token::Comma, "','")?;
                 }
+                (column, t) => Err(format!(
+                    "column {column}: expected an operator or '}}', found {t:?}"
+                ))?,
             }
-            Condition::None(outcome) => Some(outcome),
         }
+        parser.finish()?;
+
+        Ok((name, conditions))
     }
+}
 
-    fn apply_range(&self, part_range: &mut PartRange) -> Option<(PartRange, &Outcome)> {
-        match self {
-            Condition::Condition(rule) => {
-                let mut new_range = part_range.clone();
-                let (current, new) = match rule.category {
-                    Category::X => (&mut part_range.x, &mut new_range.x),
-                    Category::M => (&mut part_range.m, &mut new_range.m),
-                    Category::A => (&mut part_range.a, &mut new_range.a),
-                    Category::S => (&mut part_range.s, &mut new_range.s),
-                };
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum Node {
+    Accept,
+    Reject,
+    // Go to `then` if the category is in the range, to `otherwise` if not.
+    Branch {
+        category: Category,
+        range: Range<u64>,
+        then: usize,
+        otherwise: usize,
+    },
+}
 
-                let intersection = rule.range.intersection(current);
-                if intersection.is_empty() {
-                    None
-                } else {
-                    if intersection.start == current.start {
-                        *current = intersection.end..current.end;
-                    } else {
-                        *current = current.start..intersection.start;
+// The workflows, compiled once from 'in' into a decision tree. Branches which can't be taken
+// (given the domain and the earlier tests) are pruned, so the tree is only valid within the domain.
+// The accepted parts make up disjoint boxes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct DecisionTree {
+    domain: PartRange,
+    nodes: Vec<Node>,
+    root: usize,
+    accepted: Vec<PartRange>,
+}
+
+impl DecisionTree {
+    fn new(workflows: &Workflows, domain: PartRange) -> Result<Self, String> {
+        let mut tree = DecisionTree {
+            domain: domain.clone(),
+            nodes: Vec::new(),
+            root: 0,
+            accepted: Vec::new(),
+        };
+        let mut path = Vec::new();
+        tree.root = tree.outcome(workflows, &Outcome::Workflow("in"), domain, &mut path)?;
+
+        Ok(tree)
+    }
+
+    fn push(&mut self, node: Node) -> usize {
+        self.nodes.push(node);
+        self.nodes.len() - 1
+    }
+
+    fn outcome<'w>(
+        &mut self,
+        workflows: &Workflows<'w>,
+        outcome: &Outcome<'w>,
+        part_range: PartRange,
+        path: &mut Vec<&'w str>,
+    ) -> Result<usize, String> {
+        match outcome {
+            Outcome::Accept => {
+                self.accepted.push(part_range);
+                Ok(self.push(Node::Accept))
+            }
+            Outcome::Reject => Ok(self.push(Node::Reject)),
+            Outcome::Workflow(workflow) => {
+                if path.contains(workflow) {
+                    return Err(format!("Cycle through workflow '{workflow}'."));
+                }
+
+                path.push(workflow);
+                let node = self.compile(workflows, workflow, 0, part_range, path)?;
+                path.pop();
+                Ok(node)
+            }
+        }
+    }
+
+    fn compile<'w>(
+        &mut self,
+        workflows: &Workflows<'w>,
+        workflow: &'w str,
+        idx: usize,
+        part_range: PartRange,
+        path: &mut Vec<&'w str>,
+    ) -> Result<usize, String> {
+        let conditions = workflows
+            .0
+            .get(workflow)
+            .ok_or_else(|| format!("Unknown workflow '{workflow}'."))?;
+
+        match conditions.get(idx) {
+            None => Err(format!("Workflow '{workflow}' has no fallback.")),
+            Some(Condition::None(outcome)) => self.outcome(workflows, outcome, part_range, path),
+            Some(Condition::Condition(rule)) => {
+                let (inside, outside) = part_range.split(rule.category, &rule.range);
+                match (inside, &outside[..]) {
+                    (None, _) => self.compile(workflows, workflow, idx + 1, part_range, path),
+                    (Some(inside), []) => self.outcome(workflows, &rule.outcome, inside, path),
+                    (Some(inside), _) => {
+                        let then = self.outcome(workflows, &rule.outcome, inside, path)?;
+                        let otherwise = self.otherwise(
+                            workflows,
+                            workflow,
+                            idx + 1,
+                            rule.category,
+                            outside,
+                            path,
+                        )?;
+                        Ok(self.push(Node::Branch {
+                            category: rule.category,
+                            range: rule.range.clone(),
+                            then,
+                            otherwise,
+                        }))
                     }
-                    *new = intersection;
-                    Some((new_range, &rule.outcome))
                 }
             }
-            Condition::None(outcome) => {
-                let new_range = part_range.clone();
-                part_range.x = 0..0;
-                Some((new_range, outcome))
+        }
+    }
+
+    // When the range of a rule is in the middle, the parts which don't match it are on both sides,
+    // which needs an extra branch.
+    fn otherwise<'w>(
+        &mut self,
+        workflows: &Workflows<'w>,
+        workflow: &'w str,
+        idx: usize,
+        category: Category,
+        outside: Vec<PartRange>,
+        path: &mut Vec<&'w str>,
+    ) -> Result<usize, String> {
+        let mut nodes = Vec::new();
+        for part_range in &outside {
+            nodes.push(self.compile(workflows, workflow, idx, part_range.clone(), path)?);
+        }
+
+        match nodes[..] {
+            [then, otherwise] => Ok(self.push(Node::Branch {
+                category,
+                range: outside[0].get(category).clone(),
+                then,
+                otherwise,
+            })),
+            _ => Ok(nodes[0]),
+        }
+    }
+
+    // Whether the part is accepted, or `None` if it is outside the domain.
+    fn classify(&self, part: &Part) -> Option<bool> {
+        if !self.domain.contains(part) {
+            return None;
+        }
+
+        let mut node = self.root;
+        loop {
+            match &self.nodes[node] {
+                Node::Accept => return Some(true),
+                Node::Reject => return Some(false),
+                Node::Branch {
+                    category,
+                    range,
+                    then,
+                    otherwise,
+                } => {
+                    node = if range.contains(&part.get(*category)) {
+                        *then
+                    } else {
+                        *otherwise
+                    };
+                }
             }
         }
     }
+
+    fn accepted_combinations(&self) -> u64 {
+        self.accepted
+            .iter()
+            .map(PartRange::possible_combinations)
+            .sum()
+    }
 }
 
-#[derive(Default, Debug)]
-#[allow(clippy::type_complexity)]
-struct Workflows<'workflows>(HashMap<&'workflows str, Vec<Condition<'workflows>>>);
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum Diagnostic<'d> {
+    MissingEntry,
+    MissingTarget {
+        workflow: &'d str,
+        target: &'d str,
+    },
+    Cycle(Vec<&'d str>),
+    Unreachable(&'d str),
+    DeadRule {
+        workflow: &'d str,
+        column: usize,
+    },
+    DeadFallback(&'d str),
+    RedundantRule {
+        workflow: &'d str,
+        column: usize,
+        outcome: &'d str,
+    },
+}
 
-impl<'workflows> Workflows<'workflows> {
-    fn new(s: &'workflows str) -> Self {
-        let mut h = HashMap::new();
-        for line in s.lines() {
-            let (name, workflow) = line.split_once('{').unwrap();
-            let rules = workflow
-                .trim_end_matches('}')
-                .split(',')
-                .map(Condition::new)
-                .collect();
-            h.insert(name, rules);
+impl std::fmt::Display for Diagnostic<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Diagnostic::MissingEntry => write!(f, "There is no 'in' workflow."),
+            Diagnostic::MissingTarget { workflow, target } => {
+                write!(
+                    f,
+                    "Workflow '{workflow}' refers to the unknown workflow '{target}'."
+                )
+            }
+            Diagnostic::Cycle(cycle) => write!(f, "Cycle: {} -> {}.", cycle.join(" -> "), cycle[0]),
+            Diagnostic::Unreachable(workflow) => {
+                write!(f, "Workflow '{workflow}' can't be reached from 'in'.")
+            }
+            Diagnostic::DeadRule { workflow, column } => {
+                write!(
+                    f,
+                    "Workflow '{workflow}', column {column}: the rule never applies."
+                )
+            }
+            Diagnostic::DeadFallback(workflow) => {
+                write!(f, "Workflow '{workflow}': the fallback is never used.")
+            }
+            Diagnostic::RedundantRule {
+                workflow,
+                column,
+                outcome,
+            } => write!(
+                f,
+                "Workflow '{workflow}', column {column}: the rule can be removed, as parts go \
+                 to '{outcome}' either way."
+            ),
         }
-
-        Self(h)
     }
+}
 
-    fn apply(&self, workflow: &str, part: Part) -> Option<&Outcome> {
-        match self
-            .0
-            .get(workflow)
-            .unwrap()
+// Look for problems in the workflows: missing or unreachable workflows, cycles, rules which never
+// apply (given the domain, and the ranges of parts reaching them from 'in') and rules which make no
+// difference.
+fn analyze<'w>(workflows: &Workflows<'w>, domain: PartRange) -> Vec<Diagnostic<'w>> {
+    let mut diagnostics = Vec::new();
+    let mut names: Vec<&str> = workflows.0.keys().copied().collect();
+    names.sort_unstable();
+    let targets = |workflow: &str| -> Vec<&'w str> {
+        let mut targets: Vec<&str> = workflows.0[workflow]
             .iter()
-            .find_map(|rule| rule.apply(part))
-        {
-            Some(Outcome::Workflow(workflow)) => self.apply(workflow, part),
-            result => result,
+            .filter_map(|condition| match condition {
+                Condition::Condition(Rule {
+                    outcome: Outcome::Workflow(target),
+                    ..
+                })
+                | Condition::None(Outcome::Workflow(target)) => Some(*target),
+                _ => None,
+            })
+            .collect();
+        targets.sort_unstable();
+        targets.dedup();
+        targets
+    };
+
+    if !workflows.0.contains_key("in") {
+        diagnostics.push(Diagnostic::MissingEntry);
+    }
+
+    for &workflow in &names {
+        for target in targets(workflow) {
+            if !workflows.0.contains_key(target) {
+                diagnostics.push(Diagnostic::MissingTarget { workflow, target });
+            }
+        }
+    }
+
+    // Depth-first search, reporting the path back to a workflow still being visited.
+    fn find_cycles<'w>(
+        workflow: &'w str,
+        targets: &dyn Fn(&str) -> Vec<&'w str>,
+        path: &mut Vec<&'w str>,
+        done: &mut Vec<&'w str>,
+        diagnostics: &mut Vec<Diagnostic<'w>>,
+    ) {
+        if let Some(idx) = path.iter().position(|&w| w == workflow) {
+            diagnostics.push(Diagnostic::Cycle(path[idx..].to_vec()));
+            return;
         }
+        if done.contains(&workflow) {
+            return;
+        }
+
+        path.push(workflow);
+        for target in targets(workflow) {
+            find_cycles(target, targets, path, done, diagnostics);
+        }
+        path.pop();
+        done.push(workflow);
+    }
+
+    let existing_targets = |workflow: &str| -> Vec<&'w str> {
+        targets(workflow)
+            .into_iter()
+            .filter(|target| workflows.0.contains_key(target))
+            .collect()
+    };
+    let mut done = Vec::new();
+    for &workflow in &names {
+        find_cycles(
+            workflow,
+            &existing_targets,
+            &mut Vec::new(),
+            &mut done,
+            &mut diagnostics,
+        );
     }
 
-    fn accept_part(&self, part: Part) -> bool {
-        match self.apply("in", part) {
-            Some(Outcome::Accept) => true,
-            Some(Outcome::Reject) => false,
-            outcome => unreachable!("{:?}", outcome),
+    // Follow the parts from 'in', keeping track of the rules which apply to at least some.
+    fn explore<'w>(
+        workflows: &Workflows<'w>,
+        workflow: &'w str,
+        part_range: PartRange,
+        path: &mut Vec<&'w str>,
+        applied: &mut HashSet<(&'w str, usize)>,
+    ) {
+        let Some(conditions) = workflows.0.get(workflow) else {
+            return;
+        };
+        if path.contains(&workflow) {
+            return;
+        }
+
+        path.push(workflow);
+        let mut remaining = vec![part_range];
+        for (idx, condition) in conditions.iter().enumerate() {
+            let mut next = Vec::new();
+            for part_range in remaining {
+                let (inside, outside, outcome) = match condition {
+                    Condition::Condition(rule) => {
+                        let (inside, outside) = part_range.split(rule.category, &rule.range);
+                        (inside, outside, &rule.outcome)
+                    }
+                    Condition::None(outcome) => (Some(part_range), Vec::new(), outcome),
+                };
+
+                if let Some(inside) = inside {
+                    applied.insert((workflow, idx));
+                    if let Outcome::Workflow(target) = outcome {
+                        explore(workflows, target, inside, path, applied);
+                    }
+                }
+                next.extend(outside);
+            }
+            remaining = next;
         }
+        path.pop();
     }
 
-    fn apply_range(&self, workflow: &str, mut part_range: PartRange) -> Vec<(PartRange, &Outcome)> {
-        let mut ranges = Vec::new();
-        for rule in self.0.get(workflow).unwrap() {
-            if let Some(result) = rule.apply_range(&mut part_range) {
-                ranges.push(result);
+    let mut applied = HashSet::new();
+    explore(workflows, "in", domain, &mut Vec::new(), &mut applied);
+
+    for &workflow in &names {
+        if !applied.iter().any(|&(w, _)| w == workflow) {
+            if workflow != "in" {
+                diagnostics.push(Diagnostic::Unreachable(workflow));
             }
+            continue;
+        }
 
-            if part_range.is_empty() {
-                break;
+        let conditions = &workflows.0[workflow];
+        let mut dead = Vec::new();
+        let mut live = Vec::new();
+        for (idx, condition) in conditions.iter().enumerate() {
+            match condition {
+                Condition::Condition(rule) if applied.contains(&(workflow, idx)) => {
+                    live.push(rule.column)
+                }
+                Condition::Condition(rule) => dead.push(rule.column),
+                Condition::None(_) if !applied.contains(&(workflow, idx)) => {
+                    diagnostics.push(Diagnostic::DeadFallback(workflow))
+                }
+                Condition::None(_) => (),
             }
         }
 
-        ranges
+        // Rules which are split in two (i.e. '!=') are only dead if both halves are.
+        dead.retain(|column| !live.contains(column));
+        dead.dedup();
+        diagnostics.extend(
+            dead.into_iter()
+                .map(|column| Diagnostic::DeadRule { workflow, column }),
+        );
+
+        // A rule is redundant if all the following ones lead to the same outcome.
+        let mut redundant = Vec::new();
+        for (idx, condition) in conditions.iter().enumerate() {
+            if let Condition::Condition(rule) = condition {
+                let same = conditions[idx + 1..].iter().all(|other| match other {
+                    Condition::Condition(other) => other.outcome == rule.outcome,
+                    Condition::None(outcome) => *outcome == rule.outcome,
+                });
+                if same && !redundant.contains(&rule.column) {
+                    redundant.push(rule.column);
+                    diagnostics.push(Diagnostic::RedundantRule {
+                        workflow,
+                        column: rule.column,
+                        outcome: rule.outcome.name(),
+                    });
+                }
+            }
+        }
     }
+
+    diagnostics
 }
 
-fn part1(input: &str) -> u64 {
-    let (rules, parts) = input.split_once("\n\n").unwrap();
-    let workflows = Workflows::new(rules);
+// Without any parts, the categories are the usual ones.
+fn parse_input(input: &str) -> Result<(Workflows<'_>, Vec<&str>, Vec<Part>), String> {
+    let (workflows, parts) = input.split_once("\n\n").unwrap_or((input, ""));
+    let (mut categories, parts) = parse_parts(parts)?;
+    if parts.is_empty() {
+        categories = vec!["x", "m", "a", "s"];
+    }
+    let workflows = Workflows::new(workflows, &categories)?;
 
-    let parts = parts
-        .lines()
-        .map(str::parse)
-        .collect::<Result<Vec<Part>, _>>()
-        .unwrap();
-    parts
-        .into_iter()
-        .filter(|&part| workflows.accept_part(part))
-        .map(Part::rating)
-        .sum::<u64>()
+    Ok((workflows, categories, parts))
 }
 
-fn part2(input: &str) -> u64 {
-    let (rules, _) = input.split_once("\n\n").unwrap();
-    let workflows = Workflows::new(rules);
+fn part1(input: &str) -> Result<u64, String> {
+    let (workflows, categories, parts) = parse_input(input)?;
+    let tree = DecisionTree::new(&workflows, PartRange::new(categories.len()))?;
 
-    let mut accumulator = 0;
-    let mut to_do = vec![(PartRange::default(), &Outcome::Workflow("in"))];
-    while let Some((part_range, outcome)) = to_do.pop() {
-        match outcome {
-            Outcome::Accept => accumulator += part_range.possible_combinations(),
-            Outcome::Reject => (),
-            Outcome::Workflow(workflow) => {
-                to_do.extend(workflows.apply_range(workflow, part_range))
-            }
-        }
-    }
+    Ok(parts
+        .iter()
+        .filter(|part| tree.classify(part) == Some(true))
+        .map(Part::rating)
+        .sum::<u64>())
+}
 
-    accumulator
+fn part2(input: &str) -> Result<u64, String> {
+    let (workflows, categories, _) = parse_input(input)?;
+    let tree = DecisionTree::new(&workflows, PartRange::new(categories.len()))?;
+
+    Ok(tree.accepted_combinations())
 }
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
     let input = std::fs::read_to_string(INPUT)?;
 
-    println!("The first answer is: {}", part1(&input));
-    println!("The second answer is: {}", part2(&input));
+    // `check` only reports the problems found in the workflows.
+    if std::env::args().nth(1).as_deref() == Some("check") {
+        let (workflows, categories, _) = parse_input(&input)?;
+        for diagnostic in analyze(&workflows, PartRange::new(categories.len())) {
+            println!("{diagnostic}");
+        }
+        return Ok(());
+    }
+
+    println!("The first answer is: {}", part1(&input)?);
+    println!("The second answer is: {}", part2(&input)?);
 
     Ok(())
 }
@@ -321,17 +859,123 @@ mod tests {
 
     #[test]
     fn test_part1() {
-        let actual = part1(EXAMPLE);
+        let actual = part1(EXAMPLE).unwrap();
         let expected = 19114;
 
         assert_eq!(expected, actual);
     }
 
+    #[test]
+    fn test_decision_tree() {
+        let (workflows, categories, parts) = parse_input(EXAMPLE).unwrap();
+        let tree = DecisionTree::new(&workflows, PartRange::new(categories.len())).unwrap();
+        let actual: Vec<Option<bool>> = parts.iter().map(|part| tree.classify(part)).collect();
+        let expected = [true, false, true, false, true].map(Some);
+        assert_eq!(expected.to_vec(), actual);
+
+        let outside = Part(vec![0, 1, 1, 1]);
+        assert_eq!(None, tree.classify(&outside));
+
+        let cycle = Workflows::new("in{x<10:a,A}\na{x<10:in,R}", &["x"]).unwrap();
+        assert!(DecisionTree::new(&cycle, PartRange::new(1)).is_err());
+    }
+
+    #[test]
+    fn test_rule_language() {
+        let (_, parts) = parse_parts("{weight=10,size=3}\n{size=5,weight=20}").unwrap();
+        assert_eq!(vec![Part(vec![10, 3]), Part(vec![20, 5])], parts);
+
+        let categories = ["weight", "size"];
+        let workflows = Workflows::new(
+            "in{weight<=10:small,size==5:A,R}\nsmall{size!=3:A,weight>=10:R,A}",
+            &categories,
+        )
+        .unwrap();
+        let tree = DecisionTree::new(&workflows, PartRange::new(2)).unwrap();
+        let classify = |weight, size| tree.classify(&Part(vec![weight, size]));
+        assert_eq!(Some(true), classify(10, 4));
+        assert_eq!(Some(false), classify(10, 3));
+        assert_eq!(Some(true), classify(9, 3));
+        assert_eq!(Some(true), classify(11, 5));
+        assert_eq!(Some(false), classify(11, 6));
+
+        // Parts on both sides of the range.
+        let workflows = Workflows::new("in{size==5:R,A}", &categories).unwrap();
+        let tree = DecisionTree::new(&workflows, PartRange::new(2)).unwrap();
+        assert_eq!(4000 * 3999, tree.accepted_combinations());
+        assert_eq!(Some(true), tree.classify(&Part(vec![1, 6])));
+
+        let error = Workflows::new("in{x<10:A,height>4:A,R}", &["x"]).unwrap_err();
+        assert_eq!("Line 1, column 11: unknown category 'height'.", error);
+        let error = Workflows::new("in{x<10:A,x=>4:A,R}", &["x"]).unwrap_err();
+        assert_eq!(
+            "Line 1, column 12: expected an operator or '}', found Assign.",
+            error
+        );
+        assert!(parse_parts("{x=1,m=2}\n{x=3}").is_err());
+
+        // Columns count characters, not bytes.
+        let error = Workflows::new("in{x<10:A,\u{a0}height>4:A,R}", &["x"]).unwrap_err();
+        assert_eq!("Line 1, column 12: unknown category 'height'.", error);
+
+        let error = parse_parts("{x=1,m=4001}").unwrap_err();
+        assert_eq!("Part 1, column 6: rating 4001 is out of range.", error);
+        assert!(parse_parts("{x=0,m=1}").is_err());
+    }
+
+    #[test]
+    fn test_analyze() {
+        let (workflows, categories, _) = parse_input(EXAMPLE).unwrap();
+        let actual = analyze(&workflows, PartRange::new(categories.len()));
+        let expected = vec![
+            Diagnostic::RedundantRule {
+                workflow: "gd",
+                column: 4,
+                outcome: "R",
+            },
+            Diagnostic::RedundantRule {
+                workflow: "lnx",
+                column: 5,
+                outcome: "A",
+            },
+        ];
+        assert_eq!(expected, actual);
+
+        let workflows = Workflows::new(
+            "in{x<10:a,x<5:R,b}\na{m>0:A,R}\nb{x>20:in,A}\nc{x<3:d,m<2:a,d}",
+            &["x", "m"],
+        )
+        .unwrap();
+        let actual = analyze(&workflows, PartRange::new(2));
+        let expected = vec![
+            Diagnostic::MissingTarget {
+                workflow: "c",
+                target: "d",
+            },
+            Diagnostic::Cycle(vec!["b", "in"]),
+            Diagnostic::DeadFallback("a"),
+            Diagnostic::Unreachable("c"),
+            Diagnostic::DeadRule {
+                workflow: "in",
+                column: 11,
+            },
+        ];
+        assert_eq!(expected, actual);
+        assert_eq!(
+            "Workflow 'in', column 11: the rule never applies.",
+            expected[4].to_string()
+        );
+    }
+
     #[test]
     fn test_part2() {
-        let actual = part2(EXAMPLE);
+        let actual = part2(EXAMPLE).unwrap();
         let expected = 167409079868000;
 
         assert_eq!(expected, actual);
+
+        // The workflows are enough, the categories are then x, m, a and s.
+        let (workflows, _) = EXAMPLE.split_once("\n\n").unwrap();
+        assert_eq!(Ok(expected), part2(workflows));
     }
 }
@@ -1,6 +1,5 @@
 use num_integer::lcm;
-use std::cell::Cell;
-use std::collections::{BTreeMap, BTreeSet, HashMap, VecDeque};
+use std::collections::{HashMap, VecDeque};
 
 const INPUT: &str = "./input/day20.txt";
 
@@ -12,264 +11,768 @@ enum Pulse {
 }
 
 type Name<'name> = &'name str;
-type Destinations<'name> = Vec<Name<'name>>;
-type Inputs<'name> = BTreeMap<Name<'name>, Cell<Pulse>>;
+type Id = usize;
+
+// The button is always the first module, and the only way to send pulses into the network.
+const BUTTON: Id = 0;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
-struct Transmission<'name> {
+struct Transmission {
     pulse: Pulse,
-    origin: Name<'name>,
-    destination: Name<'name>,
+    origin: Id,
+    destination: Id,
 }
 
-impl Default for Transmission<'_> {
-    fn default() -> Self {
-        Self {
-            pulse: Pulse::Low,
-            origin: "button",
-            destination: "broadcaster",
-        }
-    }
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Kind {
+    Button,
+    Broadcaster,
+    FlipFlop,
+    Conjunction,
+    // Modules which are only ever destinations (e.g. 'rx').
+    Untyped,
 }
 
-#[derive(Debug, Default, Clone, PartialEq, Eq, Hash)]
-struct Broadcaster<'name> {
-    name: Name<'name>,
-    destinations: Destinations<'name>,
+// The static description of the network. Modules are referred to by their index, and the names
+// are only kept for display purposes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct ModuleConfiguration<'name> {
+    names: Vec<Name<'name>>,
+    ids: HashMap<Name<'name>, Id>,
+    kinds: Vec<Kind>,
+    destinations: Vec<Vec<Id>>,
+    inputs: Vec<Vec<Id>>,
 }
 
-impl<'name> Broadcaster<'name> {
-    fn recv(&self, transmission: Transmission) -> impl Iterator<Item = Transmission> {
-        self.destinations.iter().map(move |d| Transmission {
-            pulse: transmission.pulse,
-            origin: self.name,
-            destination: d,
-        })
+impl<'name> ModuleConfiguration<'name> {
+    fn intern(&mut self, name: Name<'name>) -> Id {
+        if let Some(&id) = self.ids.get(name) {
+            return id;
+        }
+
+        let id = self.names.len();
+        self.names.push(name);
+        self.ids.insert(name, id);
+        self.kinds.push(Kind::Untyped);
+        self.destinations.push(Vec::new());
+        self.inputs.push(Vec::new());
+        id
     }
-}
 
-#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
-enum FlipFlopState {
-    #[default]
-    Off,
-    On,
-}
+    fn id(&self, name: &str) -> Option<Id> {
+        self.ids.get(name).copied()
+    }
 
-#[derive(Debug, Default, Clone, PartialEq, Eq)]
-struct FlipFlop<'name> {
-    name: Name<'name>,
-    state: Cell<FlipFlopState>,
-    destinations: Destinations<'name>,
-}
+    fn len(&self) -> usize {
+        self.names.len()
+    }
+
+    fn describe(&self, transmission: Transmission) -> String {
+        let pulse = match transmission.pulse {
+            Pulse::Low => "low",
+            Pulse::High => "high",
+        };
+
+        format!(
+            "{} -{pulse}-> {}",
+            self.names[transmission.origin], self.names[transmission.destination]
+        )
+    }
+
+    // The flip-flops, then the conjunction memories, in module order.
+    fn bits(&self, state: &State) -> Vec<bool> {
+        let flip_flops = (0..self.len())
+            .filter(|&id| self.kinds[id] == Kind::FlipFlop)
+            .map(|id| state.flip_flops[id]);
+        let memories = state
+            .memories
+            .iter()
+            .flatten()
+            .map(|&pulse| pulse == Pulse::High);
+
+        flip_flops.chain(memories).collect()
+    }
 
-impl<'name> FlipFlop<'name> {
-    fn recv(
-        &'name self,
-        transmission: Transmission,
-    ) -> Box<dyn Iterator<Item = Transmission> + 'name> {
-        match transmission.pulse {
-            Pulse::High => Box::new(std::iter::empty()),
-            Pulse::Low => {
-                let pulse = match self.state.get() {
-                    FlipFlopState::Off => {
-                        self.state.replace(FlipFlopState::On);
-                        Pulse::High
-                    }
-                    FlipFlopState::On => {
-                        self.state.replace(FlipFlopState::Off);
-                        Pulse::Low
-                    }
-                };
-
-                Box::new(self.destinations.iter().map(move |d| Transmission {
-                    pulse,
-                    origin: self.name,
-                    destination: d,
-                }))
+    // Encode a snapshot as "presses/bits", with the bits in hexadecimal.
+    fn encode(&self, snapshot: &Snapshot) -> String {
+        let bits = self.bits(&snapshot.state);
+        let digits: String = bits
+            .chunks(4)
+            .map(|chunk| {
+                let digit = chunk
+                    .iter()
+                    .enumerate()
+                    .fold(0, |acc, (idx, &bit)| acc | (u32::from(bit) << idx));
+                char::from_digit(digit, 16).unwrap()
+            })
+            .collect();
+
+        format!("{}/{digits}", snapshot.presses)
+    }
+
+    fn decode(&self, s: &str) -> Result<Snapshot, String> {
+        let (presses, digits) = s
+            .trim()
+            .split_once('/')
+            .ok_or_else(|| format!("Invalid snapshot: {s}."))?;
+        let presses = presses
+            .parse()
+            .map_err(|e| format!("Invalid snapshot: {s} ({e})."))?;
+        let mut bits = digits
+            .chars()
+            .map(|c| c.to_digit(16).ok_or(format!("Invalid snapshot: {s}.")))
+            .collect::<Result<Vec<u32>, _>>()?
+            .into_iter()
+            .flat_map(|digit| (0..4).map(move |idx| digit & (1 << idx) != 0));
+
+        let mut state = State::new(self);
+        let expected = self.bits(&state).len();
+        if digits.len() != expected.div_ceil(4) {
+            return Err(format!("Invalid snapshot length: {s}."));
+        }
+
+        for id in (0..self.len()).filter(|&id| self.kinds[id] == Kind::FlipFlop) {
+            state.flip_flops[id] = bits.next().unwrap_or_default();
+        }
+        for pulse in state.memories.iter_mut().flatten() {
+            if bits.next().unwrap_or_default() {
+                *pulse = Pulse::High;
             }
         }
-    }
-}
 
-#[derive(Debug, Default, Clone, PartialEq, Eq)]
-struct Conjunction<'name> {
-    name: Name<'name>,
-    inputs: Inputs<'name>,
-    destinations: Destinations<'name>,
+        Ok(Snapshot { state, presses })
+    }
 }
 
-impl<'name> Conjunction<'name> {
-    fn recv(&self, transmission: Transmission) -> impl Iterator<Item = Transmission> {
-        self.inputs
-            .get(transmission.origin)
-            .unwrap()
-            .replace(transmission.pulse);
+fn parse_input(input: &str) -> ModuleConfiguration<'_> {
+    let mut configuration = ModuleConfiguration {
+        names: Vec::new(),
+        ids: HashMap::new(),
+        kinds: Vec::new(),
+        destinations: Vec::new(),
+        inputs: Vec::new(),
+    };
+    configuration.intern("button");
+    configuration.kinds[BUTTON] = Kind::Button;
+    let broadcaster = configuration.intern("broadcaster");
+    configuration.destinations[BUTTON].push(broadcaster);
+    configuration.inputs[broadcaster].push(BUTTON);
 
-        let pulse = if self.inputs.values().all(|pulse| pulse.get() == Pulse::High) {
-            Pulse::Low
+    for line in input.lines() {
+        let (name, destinations) = line.split_once(" -> ").unwrap();
+        let (kind, name) = if name == "broadcaster" {
+            (Kind::Broadcaster, name)
+        } else if let Some(name) = name.strip_prefix('%') {
+            (Kind::FlipFlop, name)
+        } else if let Some(name) = name.strip_prefix('&') {
+            (Kind::Conjunction, name)
         } else {
-            Pulse::High
+            unreachable!("Invalid input: {line}");
         };
 
-        self.destinations.iter().map(move |d| Transmission {
-            pulse,
-            origin: self.name,
-            destination: d,
-        })
+        let id = configuration.intern(name);
+        configuration.kinds[id] = kind;
+        for destination in destinations.trim().split(',').map(|d| d.trim()) {
+            let destination = configuration.intern(destination);
+            configuration.destinations[id].push(destination);
+            configuration.inputs[destination].push(id);
+        }
+    }
+
+    configuration
+}
+
+// Everything that changes when pressing the button: the flip-flops (on or off) and the last pulse
+// each conjunction received from each of its inputs (in the order of `inputs`).
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct State {
+    flip_flops: Vec<bool>,
+    memories: Vec<Vec<Pulse>>,
+}
+
+impl State {
+    fn new(configuration: &ModuleConfiguration) -> Self {
+        State {
+            flip_flops: vec![false; configuration.len()],
+            memories: configuration
+                .kinds
+                .iter()
+                .zip(&configuration.inputs)
+                .map(|(&kind, inputs)| match kind {
+                    Kind::Conjunction => vec![Pulse::Low; inputs.len()],
+                    _ => Vec::new(),
+                })
+                .collect(),
+        }
     }
 }
 
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct Snapshot {
+    state: State,
+    presses: u64,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+struct PulseCounts {
+    low: u64,
+    high: u64,
+}
+
 #[derive(Debug, Clone, PartialEq, Eq)]
-enum Module<'name> {
-    Broadcaster(Broadcaster<'name>),
-    FlipFlop(FlipFlop<'name>),
-    Conjunction(Conjunction<'name>),
+struct Network<'name> {
+    configuration: ModuleConfiguration<'name>,
+    state: State,
+    presses: u64,
 }
 
-impl<'name> Module<'name> {
-    fn new(s: &'name str) -> Self {
-        let (name, destination) = s.split_once(" -> ").unwrap();
-        let destinations = destination.trim().split(',').map(|d| d.trim()).collect();
-        if name == "broadcaster" {
-            Module::Broadcaster(Broadcaster { name, destinations })
-        } else if let Some(name) = name.strip_prefix('%') {
-            Module::FlipFlop(FlipFlop {
-                name,
-                state: Cell::new(FlipFlopState::Off),
-                destinations,
-            })
-        } else if let Some(name) = name.strip_prefix('&') {
-            Module::Conjunction(Conjunction {
-                name,
-                inputs: Inputs::default(),
-                destinations,
-            })
-        } else {
-            unreachable!("Invalid input: {s}");
+impl<'name> Network<'name> {
+    fn new(configuration: ModuleConfiguration<'name>) -> Self {
+        let state = State::new(&configuration);
+        Network {
+            configuration,
+            state,
+            presses: 0,
         }
     }
 
-    fn name(&self) -> Name<'name> {
-        match self {
-            Module::Broadcaster(m) => m.name,
-            Module::FlipFlop(m) => m.name,
-            Module::Conjunction(m) => m.name,
+    fn reset(&mut self) {
+        self.state = State::new(&self.configuration);
+        self.presses = 0;
+    }
+
+    fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            state: self.state.clone(),
+            presses: self.presses,
         }
     }
 
-    fn destinations(&self) -> impl Iterator<Item = &Name<'name>> {
-        match self {
-            Module::Broadcaster(m) => m.destinations.iter(),
-            Module::FlipFlop(m) => m.destinations.iter(),
-            Module::Conjunction(m) => m.destinations.iter(),
+    fn restore(&mut self, snapshot: &Snapshot) {
+        self.state = snapshot.state.clone();
+        self.presses = snapshot.presses;
+    }
+
+    // Press the button once, calling `hook` on every transmission, in order.
+    fn press_with<F: FnMut(Transmission)>(&mut self, mut hook: F) {
+        self.presses += 1;
+        let mut transmissions: VecDeque<Transmission> = self.configuration.destinations[BUTTON]
+            .iter()
+            .map(|&destination| Transmission {
+                pulse: Pulse::Low,
+                origin: BUTTON,
+                destination,
+            })
+            .collect();
+
+        while let Some(transmission) = transmissions.pop_front() {
+            hook(transmission);
+
+            if let Some(pulse) = self.recv(transmission) {
+                let origin = transmission.destination;
+                transmissions.extend(self.configuration.destinations[origin].iter().map(
+                    |&destination| Transmission {
+                        pulse,
+                        origin,
+                        destination,
+                    },
+                ));
+            }
         }
     }
 
-    fn recv(&self, transmission: Transmission) -> Box<dyn Iterator<Item = Transmission> + '_> {
-        match self {
-            Module::Broadcaster(module) => Box::new(module.recv(transmission)),
-            Module::FlipFlop(module) => module.recv(transmission),
-            Module::Conjunction(module) => Box::new(module.recv(transmission)),
+    // The pulse sent by the destination module, if any.
+    fn recv(&mut self, transmission: Transmission) -> Option<Pulse> {
+        let module = transmission.destination;
+        match (self.configuration.kinds[module], transmission.pulse) {
+            (Kind::Broadcaster, pulse) => Some(pulse),
+            (Kind::FlipFlop, Pulse::Low) => {
+                let state = &mut self.state.flip_flops[module];
+                *state = !*state;
+                Some(if *state { Pulse::High } else { Pulse::Low })
+            }
+            (Kind::Conjunction, pulse) => {
+                let slot = self.configuration.inputs[module]
+                    .iter()
+                    .position(|&input| input == transmission.origin)
+                    .unwrap();
+                let memory = &mut self.state.memories[module];
+                memory[slot] = pulse;
+
+                if memory.iter().all(|&pulse| pulse == Pulse::High) {
+                    Some(Pulse::Low)
+                } else {
+                    Some(Pulse::High)
+                }
+            }
+            _ => None,
         }
     }
-}
 
-type ModuleConfiguration<'modules> = HashMap<&'modules str, Module<'modules>>;
+    fn count_pulses(&mut self, button_presses: u64) -> PulseCounts {
+        let mut counts = PulseCounts::default();
+        for _ in 0..button_presses {
+            self.press_with(|transmission| match transmission.pulse {
+                Pulse::Low => counts.low += 1,
+                Pulse::High => counts.high += 1,
+            });
+        }
 
-fn parse_input(input: &str) -> ModuleConfiguration<'_> {
-    let mut inputs: BTreeMap<Name, Inputs> = BTreeMap::new();
-    let mut modules = ModuleConfiguration::new();
+        counts
+    }
 
-    for line in input.lines() {
-        let module = Module::new(line);
-        let name = module.name();
-        for destination in module.destinations() {
-            inputs
-                .entry(destination)
-                .or_default()
-                .insert(name, Cell::new(Pulse::Low));
+    // A log of the transmissions (as in the puzzle description), one press at a time, each
+    // preceded and followed by the encoded state (on lines starting with '#').
+    fn trace(&mut self, button_presses: u64) -> String {
+        let mut log = format!("# {}\n", self.configuration.encode(&self.snapshot()));
+        for _ in 0..button_presses {
+            let mut transmissions = Vec::new();
+            self.press_with(|transmission| transmissions.push(transmission));
+            for transmission in transmissions {
+                log.push_str(&self.configuration.describe(transmission));
+                log.push('\n');
+            }
+            log.push_str(&format!(
+                "# {}\n",
+                self.configuration.encode(&self.snapshot())
+            ));
         }
-        modules.insert(name, module);
+
+        log
     }
 
-    // We need to add the input to the conjunction modules.
-    for (name, others) in inputs {
-        if let Some(Module::Conjunction(module)) = modules.get_mut(name) {
-            module.inputs = others;
+    // Check the simulation against a log, pressing the button as needed, and return the number of
+    // presses. A state line before any transmission is restored, the others must match. Blank
+    // lines are ignored, so the examples from the puzzle description can be replayed as is.
+    fn replay(&mut self, log: &str) -> Result<u64, String> {
+        let mut pending = VecDeque::new();
+        let mut started = false;
+        let mut start = self.presses;
+
+        for (idx, line) in log.lines().map(str::trim).enumerate() {
+            if let Some(encoded) = line.strip_prefix('#') {
+                let snapshot = self.configuration.decode(encoded)?;
+                if !started {
+                    self.restore(&snapshot);
+                    start = self.presses;
+                } else if !pending.is_empty() || snapshot != self.snapshot() {
+                    return Err(format!(
+                        "Line {}: expected state {}, got {}.",
+                        idx + 1,
+                        encoded.trim(),
+                        self.configuration.encode(&self.snapshot())
+                    ));
+                }
+            } else if !line.is_empty() {
+                if pending.is_empty() {
+                    let mut transmissions = Vec::new();
+                    self.press_with(|transmission| transmissions.push(transmission));
+                    pending.extend(transmissions);
+                }
+
+                started = true;
+                let actual = self.configuration.describe(pending.pop_front().unwrap());
+                if actual != line {
+                    return Err(format!(
+                        "Line {} (press {}): expected '{line}', got '{actual}'.",
+                        idx + 1,
+                        self.presses
+                    ));
+                }
+            }
+        }
+
+        if !pending.is_empty() {
+            return Err(format!(
+                "The log stops in the middle of press {}.",
+                self.presses
+            ));
         }
+
+        Ok(self.presses - start)
     }
 
-    modules
+    // The first press (from the current state) during which the module sends the pulse, if it
+    // does so within `limit` presses.
+    fn first_emission(&mut self, module: Id, pulse: Pulse, limit: u64) -> Option<u64> {
+        for _ in 0..limit {
+            let mut emitted = false;
+            self.press_with(|transmission| {
+                emitted |= transmission.origin == module && transmission.pulse == pulse;
+            });
+
+            if emitted {
+                return Some(self.presses);
+            }
+        }
+
+        None
+    }
 }
 
 fn part1(module_configuration: ModuleConfiguration, button_presses: u64) -> u64 {
-    let mut low_pulses = 0;
-    let mut high_pulses = 0;
+    let counts = Network::new(module_configuration).count_pulses(button_presses);
+    counts.low * counts.high
+}
+
+// A binary counter: a chain of flip-flops (least significant bit first) fed by the broadcaster,
+// with a conjunction (the hub) reading some of the bits. Once they are all on, the hub resets the
+// counter and, through an odd number of inverters, sends a high pulse out.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Counter {
+    flip_flops: Vec<Id>,
+    hub: Id,
+    inverters: Vec<Id>,
+    period: u64,
+}
 
-    for _ in 0..button_presses {
-        let mut transmissions = VecDeque::from([Transmission::default()]);
+impl Counter {
+    // The module feeding the rest of the network.
+    fn output(&self) -> Id {
+        self.inverters.last().copied().unwrap_or(self.hub)
+    }
 
-        while let Some(transmission) = transmissions.pop_front() {
-            match transmission.pulse {
-                Pulse::Low => low_pulses += 1,
-                Pulse::High => high_pulses += 1,
+    fn modules(&self) -> impl Iterator<Item = Id> + '_ {
+        self.flip_flops
+            .iter()
+            .copied()
+            .chain(Some(self.hub))
+            .chain(self.inverters.iter().copied())
+    }
+
+    fn new(configuration: &ModuleConfiguration, start: Id, target: Id) -> Result<Self, String> {
+        let name = |id: Id| configuration.names[id];
+        let of_kind = |id: Id, kind: Kind| {
+            configuration.destinations[id]
+                .iter()
+                .copied()
+                .filter(move |&d| configuration.kinds[d] == kind)
+        };
+
+        // Follow the chain of flip-flops.
+        let mut flip_flops = vec![start];
+        let mut hubs = Vec::new();
+        loop {
+            let current = *flip_flops.last().unwrap();
+            if configuration.kinds[current] != Kind::FlipFlop {
+                return Err(format!("'{}' should be a flip-flop.", name(current)));
             }
+            hubs.extend(of_kind(current, Kind::Conjunction));
+
+            match of_kind(current, Kind::FlipFlop).collect::<Vec<Id>>()[..] {
+                [] => break,
+                [next] if !flip_flops.contains(&next) => flip_flops.push(next),
+                _ => {
+                    return Err(format!(
+                        "'{}' does not lead to a single new flip-flop.",
+                        name(current)
+                    ))
+                }
+            }
+        }
+
+        hubs.sort_unstable();
+        hubs.dedup();
+        let [hub] = hubs[..] else {
+            return Err(format!(
+                "The flip-flops starting at '{}' should feed a single conjunction.",
+                name(start)
+            ));
+        };
+
+        let bits: Vec<bool> = flip_flops
+            .iter()
+            .map(|&ff| configuration.destinations[ff].contains(&hub))
+            .collect();
+        let period = bits
+            .iter()
+            .rev()
+            .fold(0, |acc, &bit| (acc << 1) | u64::from(bit));
+
+        // Once the counter reaches its period, the hub needs to bump it to the next power of two,
+        // i.e. zero: that is, set the low bits to one and increment.
+        let mut resets: Vec<Id> = of_kind(hub, Kind::FlipFlop).collect();
+        let mut expected: Vec<Id> = flip_flops
+            .iter()
+            .zip(&bits)
+            .enumerate()
+            .filter_map(|(idx, (&ff, &bit))| (idx == 0 || !bit).then_some(ff))
+            .collect();
+        resets.sort_unstable();
+        expected.sort_unstable();
+        if resets != expected {
+            return Err(format!(
+                "The conjunction '{}' does not reset its counter.",
+                name(hub)
+            ));
+        }
 
-            if let Some(module) = module_configuration.get(transmission.destination) {
-                transmissions.extend(module.recv(transmission));
+        // Then, from the hub to the target.
+        let mut inverters = Vec::new();
+        let mut current = hub;
+        loop {
+            match of_kind(current, Kind::Conjunction).collect::<Vec<Id>>()[..] {
+                [next] if next == target => break,
+                [next] if configuration.inputs[next] == [current] => {
+                    inverters.push(next);
+                    current = next;
+                }
+                _ => {
+                    return Err(format!(
+                        "'{}' does not lead to '{}' through inverters.",
+                        name(current),
+                        name(target)
+                    ))
+                }
             }
         }
+
+        if inverters.len() % 2 == 0 {
+            return Err(format!(
+                "'{}' sends low pulses to '{}' when its counter resets.",
+                name(hub),
+                name(target)
+            ));
+        }
+
+        Ok(Counter {
+            flip_flops,
+            hub,
+            inverters,
+            period,
+        })
+    }
+}
+
+// Decompose the network into independent counters, all feeding the conjunction which sends
+// pulses to 'rx'.
+fn counters(configuration: &ModuleConfiguration) -> Result<(Id, Vec<Counter>), String> {
+    let rx = configuration.id("rx").ok_or("There is no 'rx' module.")?;
+    let target = match configuration.inputs[rx][..] {
+        [target] if configuration.kinds[target] == Kind::Conjunction => target,
+        _ => Err("'rx' should have a single conjunction as input.")?,
+    };
+    let broadcaster = configuration
+        .id("broadcaster")
+        .ok_or("There is no broadcaster.")?;
+
+    let counters = configuration.destinations[broadcaster]
+        .iter()
+        .map(|&start| Counter::new(configuration, start, target))
+        .collect::<Result<Vec<Counter>, _>>()?;
+
+    let mut modules: Vec<Id> = counters.iter().flat_map(Counter::modules).collect();
+    let total = modules.len();
+    modules.sort_unstable();
+    modules.dedup();
+    if modules.len() != total {
+        return Err("The counters are not independent.".to_string());
     }
 
-    low_pulses * high_pulses
+    let mut outputs: Vec<Id> = counters.iter().map(Counter::output).collect();
+    let mut inputs = configuration.inputs[target].clone();
+    outputs.sort_unstable();
+    inputs.sort_unstable();
+    if outputs != inputs {
+        return Err(format!(
+            "The inputs of '{}' are not all counters.",
+            configuration.names[target]
+        ));
+    }
+
+    Ok((target, counters))
 }
 
-fn part2(module_configuration: ModuleConfiguration) -> u64 {
-    // Hard-coding it a bit, but we do what we have to do.
-    // Looking at the input data, for 'rx' to receive a low pulse, the conjunction that has it as a
-    // destination module needs to receive high pulses on each of its inputs. Assuming these are on
-    // a cycle, let's find how many cycles for each to return to sending a high pulse to the
-    // conjunction module, then find the lowest common multiple of these periods.
-    if let Some(Module::Conjunction(module)) = module_configuration
-        .values()
-        .find(|module| module.destinations().any(|d| d == &"rx"))
-    {
-        let targets: BTreeSet<Name> = module.inputs.keys().copied().collect();
-        let mut periods = BTreeMap::new();
-        let mut transmissions = VecDeque::new();
-
-        for counter in 1.. {
-            transmissions.push_back(Transmission::default());
-
-            while let Some(transmission) = transmissions.pop_front() {
-                if transmission.pulse == Pulse::High && targets.contains(transmission.origin) {
-                    periods.entry(transmission.origin).or_insert(counter);
-
-                    // We have found all necessary periods.
-                    if periods.len() == targets.len() {
-                        return periods.into_values().fold(1, lcm);
-                    }
-                }
+// The label (with the prefix from the input), shape and colour of each module in the exports.
+fn label(configuration: &ModuleConfiguration, id: Id) -> String {
+    match configuration.kinds[id] {
+        Kind::FlipFlop => format!("%{}", configuration.names[id]),
+        Kind::Conjunction => format!("&{}", configuration.names[id]),
+        _ => configuration.names[id].to_string(),
+    }
+}
 
-                if let Some(module) = module_configuration.get(transmission.destination) {
-                    transmissions.extend(module.recv(transmission));
-                }
-            }
+fn colour(kind: Kind) -> &'static str {
+    match kind {
+        Kind::Button => "#ffffff",
+        Kind::Broadcaster => "#ffd700",
+        Kind::FlipFlop => "#add8e6",
+        Kind::Conjunction => "#fa8072",
+        Kind::Untyped => "#d3d3d3",
+    }
+}
+
+// Export the network as a Graphviz graph, with the counters (if any) as clusters.
+fn to_dot(configuration: &ModuleConfiguration, counters: &[Counter]) -> String {
+    let node = |id: Id| {
+        let shape = match configuration.kinds[id] {
+            Kind::Button => "plaintext",
+            Kind::Broadcaster => "doublecircle",
+            Kind::FlipFlop => "box",
+            Kind::Conjunction => "diamond",
+            Kind::Untyped => "ellipse",
+        };
+        format!(
+            "m{id} [label=\"{}\", shape={shape}, fillcolor=\"{}\"];",
+            label(configuration, id),
+            colour(configuration.kinds[id])
+        )
+    };
+
+    let mut dot = String::from("digraph modules {\n    node [style=filled];\n");
+    for (idx, counter) in counters.iter().enumerate() {
+        dot.push_str(&format!(
+            "    subgraph cluster_{idx} {{\n        label=\"counter {idx} (period {})\";\n",
+            counter.period
+        ));
+        for id in counter.modules() {
+            dot.push_str(&format!("        {}\n", node(id)));
+        }
+        dot.push_str("    }\n");
+    }
+
+    let clustered: Vec<Id> = counters.iter().flat_map(Counter::modules).collect();
+    for id in (0..configuration.len()).filter(|id| !clustered.contains(id)) {
+        dot.push_str(&format!("    {}\n", node(id)));
+    }
+
+    for (origin, destinations) in configuration.destinations.iter().enumerate() {
+        for destination in destinations {
+            dot.push_str(&format!("    m{origin} -> m{destination};\n"));
         }
     }
 
-    0
+    dot.push_str("}\n");
+    dot
+}
+
+// Export the network as a Mermaid flowchart, with the counters (if any) as subgraphs.
+fn to_mermaid(configuration: &ModuleConfiguration, counters: &[Counter]) -> String {
+    let node = |id: Id| {
+        let label = label(configuration, id);
+        match configuration.kinds[id] {
+            Kind::Button => format!("m{id}>\"{label}\"]"),
+            Kind::Broadcaster => format!("m{id}((\"{label}\"))"),
+            Kind::FlipFlop => format!("m{id}[\"{label}\"]"),
+            Kind::Conjunction => format!("m{id}{{\"{label}\"}}"),
+            Kind::Untyped => format!("m{id}([\"{label}\"])"),
+        }
+    };
+
+    let mut mermaid = String::from("flowchart LR\n");
+    for (idx, counter) in counters.iter().enumerate() {
+        mermaid.push_str(&format!(
+            "    subgraph counter{idx} [\"counter {idx} (period {})\"]\n",
+            counter.period
+        ));
+        for id in counter.modules() {
+            mermaid.push_str(&format!("        {}\n", node(id)));
+        }
+        mermaid.push_str("    end\n");
+    }
+
+    let clustered: Vec<Id> = counters.iter().flat_map(Counter::modules).collect();
+    for id in (0..configuration.len()).filter(|id| !clustered.contains(id)) {
+        mermaid.push_str(&format!("    {}\n", node(id)));
+    }
+
+    for (origin, destinations) in configuration.destinations.iter().enumerate() {
+        for destination in destinations {
+            mermaid.push_str(&format!("    m{origin} --> m{destination}\n"));
+        }
+    }
+
+    for kind in [
+        Kind::Button,
+        Kind::Broadcaster,
+        Kind::FlipFlop,
+        Kind::Conjunction,
+        Kind::Untyped,
+    ] {
+        let class = format!("{kind:?}").to_lowercase();
+        let ids: Vec<String> = (0..configuration.len())
+            .filter(|&id| configuration.kinds[id] == kind)
+            .map(|id| format!("m{id}"))
+            .collect();
+        if !ids.is_empty() {
+            mermaid.push_str(&format!(
+                "    classDef {class} fill:{}\n    class {} {class}\n",
+                colour(kind),
+                ids.join(",")
+            ));
+        }
+    }
+
+    mermaid
+}
+
+fn part2(module_configuration: ModuleConfiguration) -> Result<u64, String> {
+    // For 'rx' to receive a low pulse, the conjunction that has it as a destination module needs
+    // to receive high pulses on each of its inputs. These are the outputs of independent binary
+    // counters, so they do so periodically, and we need the lowest common multiple of the periods.
+    let (_, counters) = counters(&module_configuration)?;
+    let mut network = Network::new(module_configuration);
+
+    // Make sure each counter starts on a clean cycle, as its period implies.
+    for counter in &counters {
+        network.reset();
+        if network.first_emission(counter.output(), Pulse::High, counter.period)
+            != Some(counter.period)
+        {
+            return Err(format!(
+                "'{}' does not fire after {} presses.",
+                network.configuration.names[counter.output()],
+                counter.period
+            ));
+        }
+    }
+
+    Ok(counters.iter().map(|counter| counter.period).fold(1, lcm))
 }
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
     let input = std::fs::read_to_string(INPUT)?;
     let module_configuration = parse_input(&input);
 
+    // For debugging: `trace <file> [presses]` writes a log, `replay <file>` checks one, and
+    // `dot <file>` or `mermaid <file>` export the network.
+    let mut args = std::env::args().skip(1);
+    match args.next().as_deref() {
+        Some("trace") => {
+            let path = args.next().ok_or("Missing trace file.")?;
+            let presses = args.next().map(|p| p.parse()).transpose()?.unwrap_or(1);
+            let mut network = Network::new(module_configuration);
+            std::fs::write(path, network.trace(presses))?;
+            return Ok(());
+        }
+        Some("replay") => {
+            let path = args.next().ok_or("Missing trace file.")?;
+            let log = std::fs::read_to_string(path)?;
+            let presses = Network::new(module_configuration).replay(&log)?;
+            println!("Replayed {presses} presses.");
+            return Ok(());
+        }
+        Some(format @ ("dot" | "mermaid")) => {
+            let path = args.next().ok_or("Missing output file.")?;
+            let counters = counters(&module_configuration)
+                .map(|(_, counters)| counters)
+                .unwrap_or_default();
+            let export = if format == "dot" {
+                to_dot(&module_configuration, &counters)
+            } else {
+                to_mermaid(&module_configuration, &counters)
+            };
+            std::fs::write(path, export)?;
+            return Ok(());
+        }
+        _ => (),
+    }
+
     println!(
         "The first answer is: {}",
         part1(module_configuration.clone(), 1000)
     );
-    println!("The second answer is: {}", part2(module_configuration));
+    println!("The second answer is: {}", part2(module_configuration)?);
 
     Ok(())
 }
@@ -294,6 +797,21 @@ mod tests {
         &con -> output\n\
     ";
 
+    // Two counters, with periods 3 (0b11) and 5 (0b101).
+    const EXAMPLE_3: &str = "\
+        broadcaster -> a0, b0\n\
+        %a0 -> a1, ha\n\
+        %a1 -> ha\n\
+        &ha -> a0, ia\n\
+        &ia -> fin\n\
+        %b0 -> b1, hb\n\
+        %b1 -> b2\n\
+        %b2 -> hb\n\
+        &hb -> b0, b1, ib\n\
+        &ib -> fin\n\
+        &fin -> rx\n\
+    ";
+
     #[test]
     fn test_part1_ex1() {
         let configuration = parse_input(EXAMPLE_1);
@@ -303,6 +821,23 @@ mod tests {
         assert_eq!(expected, actual);
     }
 
+    #[test]
+    fn test_network() {
+        let mut network = Network::new(parse_input(EXAMPLE_2));
+        let expected = PulseCounts { low: 4, high: 4 };
+        assert_eq!(expected, network.count_pulses(1));
+        assert_ne!(expected, network.count_pulses(1));
+
+        network.reset();
+        assert_eq!(0, network.presses);
+        assert_eq!(expected, network.count_pulses(1));
+
+        // 'con' sees both its inputs high at the end of the first press.
+        network.reset();
+        let con = network.configuration.id("con").unwrap();
+        assert_eq!(Some(1), network.first_emission(con, Pulse::Low, 10));
+    }
+
     #[test]
     fn test_part1_ex2() {
         let configuration = parse_input(EXAMPLE_2);
@@ -311,4 +846,99 @@ mod tests {
 
         assert_eq!(expected, actual);
     }
+
+    #[test]
+    fn test_snapshot() {
+        let mut network = Network::new(parse_input(EXAMPLE_2));
+        network.count_pulses(3);
+        let snapshot = network.snapshot();
+        let encoded = network.configuration.encode(&snapshot);
+        assert_eq!(Ok(snapshot.clone()), network.configuration.decode(&encoded));
+
+        let expected = network.count_pulses(5);
+        network.restore(&snapshot);
+        assert_eq!(expected, network.count_pulses(5));
+        assert_eq!(8, network.presses);
+    }
+
+    #[test]
+    fn test_replay() {
+        // From the puzzle description.
+        const LOG: &str = "\
+            button -low-> broadcaster\n\
+            broadcaster -low-> a\n\
+            broadcaster -low-> b\n\
+            broadcaster -low-> c\n\
+            a -high-> b\n\
+            b -high-> c\n\
+            c -high-> inv\n\
+            inv -low-> a\n\
+            a -low-> b\n\
+            b -low-> c\n\
+            c -low-> inv\n\
+            inv -high-> a\n\
+        ";
+        let mut network = Network::new(parse_input(EXAMPLE_1));
+        assert_eq!(Ok(1), network.replay(LOG));
+
+        let mut network = Network::new(parse_input(EXAMPLE_2));
+        let log = network.trace(4);
+        network.reset();
+        assert_eq!(Ok(4), network.replay(&log));
+        assert!(network
+            .replay(&log.replace("-high-> con", "-low-> con"))
+            .is_err());
+
+        // The log starts from its own snapshot, whatever the state of the network.
+        let mut network = Network::new(parse_input(EXAMPLE_2));
+        network.count_pulses(3);
+        let log = network.trace(2);
+        network.reset();
+        assert_eq!(Ok(2), network.replay(&log));
+        network.count_pulses(5);
+        assert_eq!(Ok(2), network.replay(&log));
+
+        // A log which stops in the middle of a press is incomplete.
+        let truncated: Vec<&str> = log.lines().filter(|line| !line.starts_with('#')).collect();
+        network.reset();
+        assert!(network
+            .replay(&truncated[..truncated.len() - 1].join("\n"))
+            .is_err());
+    }
+
+    #[test]
+    fn test_export() {
+        let configuration = parse_input(EXAMPLE_3);
+        let (_, counters) = counters(&configuration).unwrap();
+        let ha = configuration.id("ha").unwrap();
+
+        let dot = to_dot(&configuration, &counters);
+        assert!(dot.contains("subgraph cluster_1 {\n        label=\"counter 1 (period 5)\";"));
+        assert!(dot.contains(&format!(
+            "m{ha} [label=\"&ha\", shape=diamond, fillcolor=\"#fa8072\"];"
+        )));
+
+        let mermaid = to_mermaid(&configuration, &counters);
+        assert!(mermaid.contains("subgraph counter0 [\"counter 0 (period 3)\"]"));
+        assert!(mermaid.contains(&format!("m{ha}{{\"&ha\"}}")));
+        assert_eq!(
+            dot.matches(" -> ").count(),
+            mermaid.matches(" --> ").count()
+        );
+    }
+
+    #[test]
+    fn test_part2() {
+        let configuration = parse_input(EXAMPLE_3);
+        let (target, counters) = counters(&configuration).unwrap();
+        let periods: Vec<u64> = counters.iter().map(|counter| counter.period).collect();
+        assert_eq!(vec![3, 5], periods);
+
+        // Check against the actual simulation.
+        let mut network = Network::new(configuration.clone());
+        assert_eq!(Some(15), network.first_emission(target, Pulse::Low, 100));
+        assert_eq!(Ok(15), part2(configuration));
+
+        assert!(part2(parse_input(EXAMPLE_1)).is_err());
+    }
 }
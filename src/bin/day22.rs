@@ -1,6 +1,6 @@
-use advent_of_code_2023::grid::Position;
-use advent_of_code_2023::range_extension::RangeExtension;
-use std::collections::{HashMap, HashSet};
+use advent_of_code_2023::grid::{Grid, Position};
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BinaryHeap, HashMap};
 use std::fmt::{Display, Formatter};
 use std::ops::Range;
 use std::str::FromStr;
@@ -8,6 +8,8 @@ use std::str::FromStr;
 const INPUT: &str = "./input/day22.txt";
 const FLOOR: usize = 0;
 
+type Id = usize;
+
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 struct Brick {
     x: Range<usize>,
@@ -24,36 +26,32 @@ impl Brick {
         (self.x.start..self.x.end)
             .flat_map(|x| (self.y.start..self.y.end).map(move |y| Position::new(x, y)))
     }
-
-    fn supports(&self, other: &Self) -> bool {
-        self != other
-            && self.z.end == other.z.start
-            && self.x.overlaps(&other.x)
-            && self.y.overlaps(&other.y)
-    }
-
-    fn is_supported(&self, other: &Self) -> bool {
-        self != other
-            && self.z.start == other.z.end
-            && self.x.overlaps(&other.x)
-            && self.y.overlaps(&other.y)
-    }
 }
 
 impl FromStr for Brick {
     type Err = String;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let (start, end) = s.split_once('~').unwrap();
-        let mut starts = start
-            .split(',')
-            .map(|value| value.parse::<usize>().unwrap());
-        let mut ends = end.split(',').map(|value| value.parse::<usize>().unwrap());
-        let x = starts.next().unwrap()..ends.next().unwrap() + 1;
-        let y = starts.next().unwrap()..ends.next().unwrap() + 1;
-        let z = starts.next().unwrap()..ends.next().unwrap() + 1;
+        let corner = |corner: &str| -> Result<[usize; 3], String> {
+            corner
+                .split(',')
+                .map(|value| value.trim().parse::<usize>())
+                .collect::<Result<Vec<_>, _>>()
+                .ok()
+                .and_then(|values| values.try_into().ok())
+                .ok_or(format!("Invalid brick: {s}."))
+        };
+        let (start, end) = s.split_once('~').ok_or(format!("Invalid brick: {s}."))?;
+        let ([x, y, z], [x_end, y_end, z_end]) = (corner(start)?, corner(end)?);
+        if x > x_end || y > y_end || z > z_end {
+            return Err(format!("Invalid brick: {s}."));
+        }
 
-        Ok(Self { x, y, z })
+        Ok(Self {
+            x: x..x_end + 1,
+            y: y..y_end + 1,
+            z: z..z_end + 1,
+        })
     }
 }
 
@@ -72,130 +70,335 @@ impl Display for Brick {
     }
 }
 
-trait Bricks {
-    fn settle(&mut self);
-    fn supported_by(&self) -> HashMap<&Brick, HashSet<&Brick>>;
-    fn supports(&self) -> HashMap<&Brick, HashSet<&Brick>>;
+// Settled bricks, identified by their index in the input, with the bricks right below and right
+// above each of them. Bricks added later get the next ids, and removed bricks leave a hole.
+#[derive(Debug, Clone)]
+struct BrickStack {
+    bricks: Vec<Option<Brick>>,
+    supported_by: Vec<Vec<Id>>,
+    supports: Vec<Vec<Id>>,
+    // The bricks in each column, by the height of their bottom.
+    columns: HashMap<Position, BTreeMap<usize, Id>>,
 }
 
-impl Bricks for Vec<Brick> {
-    fn settle(&mut self) {
-        let mut heights = HashMap::new();
+impl BrickStack {
+    fn new(mut bricks: Vec<Brick>) -> Self {
+        let width = bricks
+            .iter()
+            .map(|brick| brick.x.end)
+            .max()
+            .unwrap_or_default();
+        let height = bricks
+            .iter()
+            .map(|brick| brick.y.end)
+            .max()
+            .unwrap_or_default();
+
+        // The top of the highest brick on each column, and which brick it is.
+        let mut heights: Grid<(usize, Option<Id>)> =
+            Grid::new(height, width, vec![(FLOOR + 1, None); width * height])
+                .expect("The grid has the right size.");
+        let mut supported_by = vec![Vec::new(); bricks.len()];
+        let mut supports = vec![Vec::new(); bricks.len()];
 
         // Settle the bricks, starting with the lowest brick.
-        self.sort_unstable_by_key(|brick| brick.z.start);
-        for brick in self.iter_mut() {
-            let minimum_z = *brick
+        let mut order: Vec<Id> = (0..bricks.len()).collect();
+        order.sort_unstable_by_key(|&id| bricks[id].z.start);
+        for id in order {
+            let brick = &mut bricks[id];
+            let minimum_z = brick
                 .surface()
-                .filter_map(|p| heights.get(&p))
+                .filter_map(|p| heights.get(p))
+                .map(|&(z, _)| z)
                 .max()
-                .unwrap_or(&(FLOOR + 1));
+                .unwrap_or(FLOOR + 1);
             brick.move_down_to(minimum_z);
+
+            for p in brick.surface() {
+                if let Some(top) = heights.get_mut(p) {
+                    if let (z, Some(other)) = *top {
+                        if z == minimum_z && !supported_by[id].contains(&other) {
+                            supported_by[id].push(other);
+                            supports[other].push(id);
+                        }
+                    }
+                    *top = (brick.z.end, Some(id));
+                }
+            }
+        }
+
+        let mut columns: HashMap<Position, BTreeMap<usize, Id>> = HashMap::new();
+        for (id, brick) in bricks.iter().enumerate() {
             for p in brick.surface() {
-                heights.insert(p, brick.z.end);
+                columns.entry(p).or_default().insert(brick.z.start, id);
+            }
+        }
+
+        Self {
+            bricks: bricks.into_iter().map(Some).collect(),
+            supported_by,
+            supports,
+            columns,
+        }
+    }
+
+    fn brick(&self, id: Id) -> Option<&Brick> {
+        self.bricks.get(id)?.as_ref()
+    }
+
+    fn ids(&self) -> impl Iterator<Item = Id> + '_ {
+        self.bricks
+            .iter()
+            .enumerate()
+            .filter_map(|(id, brick)| brick.as_ref().map(|_| id))
+    }
+
+    // Drop an extra brick onto the stack, from where it is.
+    fn insert(&mut self, brick: Brick) -> Result<Id, String> {
+        let overlaps = brick
+            .surface()
+            .filter_map(|p| self.below(p, brick.z.end))
+            .filter_map(|other| self.brick(other))
+            .any(|other| other.z.end > brick.z.start);
+        if overlaps {
+            return Err(format!("The brick {brick} overlaps another brick."));
+        }
+
+        let id = self.bricks.len();
+        self.bricks.push(None);
+        self.supported_by.push(Vec::new());
+        self.supports.push(Vec::new());
+        self.place(id, brick);
+        self.settle(vec![id]);
+
+        Ok(id)
+    }
+
+    // Disintegrate a brick, and let the bricks above fall.
+    fn remove(&mut self, id: Id) -> Result<Brick, String> {
+        let (brick, supported) = self.lift(id).ok_or(format!("Invalid brick: {id}."))?;
+        self.settle(supported);
+
+        Ok(brick)
+    }
+
+    // Drop the given bricks and everything resting on them as low as they can go. Processing the
+    // lowest brick first means that everything below it has already settled.
+    fn settle(&mut self, bricks: Vec<Id>) {
+        let mut queue: BinaryHeap<_> = bricks
+            .into_iter()
+            .filter_map(|id| Some(Reverse((self.brick(id)?.z.start, id))))
+            .collect();
+
+        while let Some(Reverse((_, id))) = queue.pop() {
+            let Some(brick) = self.brick(id) else {
+                continue;
+            };
+            let minimum_z = self.landing(brick);
+            if minimum_z == brick.z.start {
+                continue;
+            }
+
+            if let Some((mut brick, supported)) = self.lift(id) {
+                brick.move_down_to(minimum_z);
+                self.place(id, brick);
+                queue.extend(
+                    supported
+                        .into_iter()
+                        .filter_map(|other| Some(Reverse((self.brick(other)?.z.start, other)))),
+                );
             }
         }
     }
 
-    fn supported_by(&self) -> HashMap<&Brick, HashSet<&Brick>> {
-        self.iter()
-            .map(|brick| {
-                (
-                    brick,
-                    self.iter().filter(|other| other.supports(brick)).collect(),
-                )
-            })
-            .collect()
+    // The highest brick in a column which starts below the given height.
+    fn below(&self, p: Position, z: usize) -> Option<Id> {
+        self.columns
+            .get(&p)?
+            .range(..z)
+            .next_back()
+            .map(|(_, &id)| id)
     }
 
-    fn supports(&self) -> HashMap<&Brick, HashSet<&Brick>> {
-        self.iter()
-            .map(|brick| {
-                (
-                    brick,
-                    self.iter()
-                        .filter(|other| other.is_supported(brick))
-                        .collect(),
-                )
-            })
-            .collect()
+    fn landing(&self, brick: &Brick) -> usize {
+        brick
+            .surface()
+            .filter_map(|p| self.below(p, brick.z.start))
+            .filter_map(|other| self.brick(other))
+            .map(|other| other.z.end)
+            .max()
+            .unwrap_or(FLOOR + 1)
+    }
+
+    fn place(&mut self, id: Id, brick: Brick) {
+        for p in brick.surface() {
+            let column = self.columns.entry(p).or_default();
+            let above = column.get(&brick.z.end).copied();
+            let below = column
+                .range(..brick.z.start)
+                .next_back()
+                .map(|(_, &other)| other);
+            column.insert(brick.z.start, id);
+
+            if let Some(other) = above {
+                if !self.supports[id].contains(&other) {
+                    self.supports[id].push(other);
+                    self.supported_by[other].push(id);
+                }
+            }
+            if let Some(other) = below {
+                let touches = self.brick(other).map(|other| other.z.end) == Some(brick.z.start);
+                if touches && !self.supported_by[id].contains(&other) {
+                    self.supported_by[id].push(other);
+                    self.supports[other].push(id);
+                }
+            }
+        }
+
+        self.bricks[id] = Some(brick);
     }
+
+    // Take a brick out of the stack, returning it with the bricks which were resting on it.
+    fn lift(&mut self, id: Id) -> Option<(Brick, Vec<Id>)> {
+        let brick = self.bricks.get_mut(id)?.take()?;
+        for p in brick.surface() {
+            if let Some(column) = self.columns.get_mut(&p) {
+                column.remove(&brick.z.start);
+            }
+        }
+
+        for other in std::mem::take(&mut self.supported_by[id]) {
+            self.supports[other].retain(|&above| above != id);
+        }
+        let supported = std::mem::take(&mut self.supports[id]);
+        for &other in &supported {
+            self.supported_by[other].retain(|&below| below != id);
+        }
+
+        Some((brick, supported))
+    }
+}
+
+fn part1(stack: &BrickStack) -> usize {
+    // Find the bricks that are not the single support of another brick.
+    stack
+        .ids()
+        .filter(|&id| {
+            stack.supports[id]
+                .iter()
+                .all(|&other| stack.supported_by[other].len() > 1)
+        })
+        .count()
 }
 
-fn part1(bricks: &Vec<Brick>) -> usize {
-    // Find the bricks that have a single supporting brick.
-    let single_support: HashSet<&Brick> = bricks
-        .supported_by()
-        .into_iter()
-        .filter_map(|(brick, supports)| {
-            if supports.len() == 1 {
-                Some(brick)
+// A brick dominates another if every chain of supports from the floor to the other brick goes
+// through it, i.e. if disintegrating it makes the other brick fall.
+#[derive(Debug, Clone)]
+struct DominatorTree {
+    // The immediate dominator of each brick, `None` standing for the floor.
+    parents: Vec<Option<Id>>,
+    depths: Vec<usize>,
+    // The number of bricks dominated by each brick.
+    falls: Vec<usize>,
+}
+
+impl DominatorTree {
+    fn new(stack: &BrickStack) -> Self {
+        let mut tree = Self {
+            parents: vec![None; stack.bricks.len()],
+            depths: vec![0; stack.bricks.len()],
+            falls: vec![0; stack.bricks.len()],
+        };
+
+        // A brick always rests on lower bricks, whose dominators are then already known. Its own
+        // dominator is the deepest common dominator of its supports.
+        let mut order: Vec<Id> = stack.ids().collect();
+        order.sort_unstable_by_key(|&id| stack.brick(id).map(|brick| brick.z.start));
+        for &id in &order {
+            let parent = stack.supported_by[id]
+                .iter()
+                .map(|&other| Some(other))
+                .reduce(|a, b| tree.common_dominator(a, b))
+                .flatten();
+            tree.parents[id] = parent;
+            tree.depths[id] = parent.map_or(0, |parent| tree.depths[parent] + 1);
+        }
+
+        // The size of each subtree, from the top down.
+        for &id in order.iter().rev() {
+            if let Some(parent) = tree.parents[id] {
+                tree.falls[parent] += tree.falls[id] + 1;
+            }
+        }
+
+        tree
+    }
+
+    fn common_dominator(&self, mut a: Option<Id>, mut b: Option<Id>) -> Option<Id> {
+        while let (Some(x), Some(y)) = (a, b) {
+            if x == y {
+                break;
+            } else if self.depths[x] >= self.depths[y] {
+                a = self.parents[x];
             } else {
-                None
+                b = self.parents[y];
             }
-        })
-        .collect();
+        }
 
-    // Now, find the bricks that are not supporting these.
-    bricks
-        .iter()
-        .filter(|brick| !single_support.iter().any(|other| brick.supports(other)))
-        .count()
+        a.filter(|_| a == b)
+    }
+
+    // The number of other bricks which fall when this one is disintegrated.
+    fn falls(&self, id: Id) -> usize {
+        self.falls[id]
+    }
 }
 
-fn part2(bricks: &Vec<Brick>) -> usize {
-    // Get the bricks supported by other bricks. Remove those that are not supported, as it would
-    // complicate matters when filtering.
-    let mut supported_by = bricks.supported_by();
-    supported_by.retain(|_, others| !others.is_empty());
-
-    // Get the bricks that support other bricks. We can remove the bricks that do not support
-    // any other, as they would not cause a chain reaction.
-    let mut supports = bricks.supports();
-    supports.retain(|_, others| !others.is_empty());
-
-    let mut result = 0;
-    for &initial in supports.keys() {
-        let mut fallen = HashSet::new();
-        let mut to_fall = HashSet::from([initial]);
-
-        // Keep adding falling bricks until no more fall.
-        while !to_fall.is_empty() {
-            fallen.extend(to_fall.iter());
-
-            to_fall = to_fall
-                .into_iter()
-                .filter_map(|falling| supports.get(falling))
-                .flat_map(|candidates| {
-                    candidates.iter().filter(|&&candidate| {
-                        !fallen.contains(candidate)
-                            && supported_by
-                                .get(candidate)
-                                .map_or(false, |others| others.is_subset(&fallen))
-                    })
-                })
-                .copied()
-                .collect();
-        }
-
-        // Remove the initial brick (it doesn't count).
-        result += fallen.len() - 1;
-    }
-
-    result
+fn part2(stack: &BrickStack) -> usize {
+    let tree = DominatorTree::new(stack);
+    stack.ids().map(|id| tree.falls(id)).sum()
 }
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
     let input = std::fs::read_to_string(INPUT)?;
-    let mut bricks = input
+    let bricks = input
         .lines()
         .map(str::parse)
         .collect::<Result<Vec<Brick>, _>>()?;
+    let mut stack = BrickStack::new(bricks);
+
+    let mut args = std::env::args().skip(1);
+    match args.next().as_deref() {
+        // `falls <brick>` counts the bricks which fall when a brick (its line in the input,
+        // starting at 0) is disintegrated.
+        Some("falls") => {
+            let id: Id = args.next().ok_or("Missing brick.")?.parse()?;
+            if stack.brick(id).is_none() {
+                return Err(format!("Invalid brick: {id}.").into());
+            }
+            println!(
+                "{} bricks would fall.",
+                DominatorTree::new(&stack).falls(id)
+            );
+            return Ok(());
+        }
+        // `remove <brick>...` disintegrates bricks one after the other, and `add <brick>...`
+        // drops extra bricks (e.g. `1,1,20~1,3,20`) onto the stack, before answering.
+        Some("remove") => {
+            for id in args {
+                stack.remove(id.parse()?)?;
+            }
+        }
+        Some("add") => {
+            for brick in args {
+                stack.insert(brick.parse()?)?;
+            }
+        }
+        _ => {}
+    }
 
-    bricks.settle();
-
-    println!("The first answer is: {}", part1(&bricks));
-    println!("The second answer is: {}", part2(&bricks));
+    println!("The first answer is: {}", part1(&stack));
+    println!("The second answer is: {}", part2(&stack));
 
     Ok(())
 }
@@ -216,31 +419,133 @@ mod tests {
 
     #[test]
     fn test_part1() {
-        let mut bricks = EXAMPLE
+        let bricks = EXAMPLE
             .lines()
             .map(str::parse)
             .collect::<Result<Vec<Brick>, _>>()
             .unwrap();
+        let stack = BrickStack::new(bricks);
 
-        bricks.settle();
-
-        let actual = part1(&bricks);
+        let actual = part1(&stack);
         let expected = 5;
 
         assert_eq!(expected, actual);
     }
 
     #[test]
-    fn test_part2() {
-        let mut bricks = EXAMPLE
+    fn test_brick_stack() {
+        let bricks = EXAMPLE
             .lines()
             .map(str::parse)
             .collect::<Result<Vec<Brick>, _>>()
             .unwrap();
+        let stack = BrickStack::new(bricks);
+
+        let settled: Vec<String> = stack
+            .bricks
+            .iter()
+            .flatten()
+            .map(Brick::to_string)
+            .collect();
+        let expected = [
+            "1,0,1~1,2,1",
+            "0,0,2~2,0,2",
+            "0,2,2~2,2,2",
+            "0,0,3~0,2,3",
+            "2,0,3~2,2,3",
+            "0,1,4~2,1,4",
+            "1,1,5~1,1,6",
+        ];
+        assert_eq!(expected.to_vec(), settled);
+
+        let expected: [&[Id]; 7] = [&[], &[0], &[0], &[1, 2], &[1, 2], &[3, 4], &[5]];
+        assert_eq!(expected.to_vec(), stack.supported_by);
+        let expected: [&[Id]; 7] = [&[1, 2], &[3, 4], &[3, 4], &[5], &[5], &[6], &[]];
+        assert_eq!(expected.to_vec(), stack.supports);
+    }
+
+    #[test]
+    fn test_incremental() {
+        let bricks = EXAMPLE
+            .lines()
+            .map(str::parse)
+            .collect::<Result<Vec<Brick>, _>>()
+            .unwrap();
+        let mut stack = BrickStack::new(bricks.clone());
+
+        // Without F, G falls onto A.
+        stack.remove(5).unwrap();
+        assert_eq!(
+            Some("1,1,2~1,1,3".to_string()),
+            stack.brick(6).map(Brick::to_string)
+        );
+        assert_eq!(vec![0], stack.supported_by[6]);
+        assert_eq!(vec![1, 2, 6], stack.supports[0]);
+        assert!(stack.remove(5).is_err());
+
+        // A plate dropped from above lands on D, E and G.
+        let plate = stack.insert("0,0,10~2,2,10".parse().unwrap()).unwrap();
+        assert_eq!(
+            Some("0,0,4~2,2,4".to_string()),
+            stack.brick(plate).map(Brick::to_string)
+        );
+        let mut supported_by = stack.supported_by[plate].clone();
+        supported_by.sort_unstable();
+        assert_eq!(vec![3, 4, 6], supported_by);
+        assert!(stack.insert("1,1,3~1,1,3".parse().unwrap()).is_err());
+        for invalid in [
+            "1,1",
+            "1,1,3~1,1",
+            "1,1,3~1,x,3",
+            "1,1,3~1,1,2",
+            "1,1,3~1,1,3,4",
+        ] {
+            assert_eq!(
+                Err(format!("Invalid brick: {invalid}.")),
+                invalid.parse::<Brick>()
+            );
+        }
+
+        // Removing A drops everything, which must match settling from scratch.
+        stack.remove(0).unwrap();
+        let mut remaining = bricks[1..5].to_vec();
+        remaining.extend([
+            "1,1,8~1,1,9".parse().unwrap(),
+            "0,0,10~2,2,10".parse().unwrap(),
+        ]);
+        let expected = BrickStack::new(remaining);
+        let actual: Vec<&Brick> = stack.bricks.iter().flatten().collect();
+        assert_eq!(expected.bricks.iter().flatten().collect::<Vec<_>>(), actual);
+        assert_eq!(part1(&expected), part1(&stack));
+        assert_eq!(part2(&expected), part2(&stack));
+    }
+
+    #[test]
+    fn test_dominator_tree() {
+        let bricks = EXAMPLE
+            .lines()
+            .map(str::parse)
+            .collect::<Result<Vec<Brick>, _>>()
+            .unwrap();
+        let stack = BrickStack::new(bricks);
+        let tree = DominatorTree::new(&stack);
+
+        let expected = [None, Some(0), Some(0), Some(0), Some(0), Some(0), Some(5)];
+        assert_eq!(expected.to_vec(), tree.parents);
+        let falls: Vec<usize> = stack.ids().map(|id| tree.falls(id)).collect();
+        assert_eq!(vec![6, 0, 0, 0, 0, 1, 0], falls);
+    }
 
-        bricks.settle();
+    #[test]
+    fn test_part2() {
+        let bricks = EXAMPLE
+            .lines()
+            .map(str::parse)
+            .collect::<Result<Vec<Brick>, _>>()
+            .unwrap();
+        let stack = BrickStack::new(bricks);
 
-        let actual = part2(&bricks);
+        let actual = part2(&stack);
         let expected = 7;
 
         assert_eq!(expected, actual);
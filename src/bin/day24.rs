@@ -1,13 +1,131 @@
+use num_integer::Integer;
+use std::cmp::Ordering;
+use std::fmt::{Display, Formatter};
 use std::num::ParseIntError;
 use std::ops::{RangeInclusive, Sub};
 use std::str::FromStr;
+#[cfg(feature = "z3")]
 use z3::ast::{Ast, Int};
+#[cfg(feature = "z3")]
 use z3::{Config, Context, Solver};
 
 const INPUT: &str = "./input/day24.txt";
 
 type Value = i64;
 type TestArea = RangeInclusive<Value>;
+type Vector = [i128; 3];
+
+// An exact fraction, always in lowest terms with a positive denominator.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+struct Rational {
+    numerator: i128,
+    denominator: i128,
+}
+
+impl Rational {
+    fn new(numerator: i128, denominator: i128) -> Self {
+        assert_ne!(denominator, 0, "The denominator cannot be zero.");
+        let gcd = numerator.gcd(&denominator) * denominator.signum();
+        Self {
+            numerator: numerator / gcd,
+            denominator: denominator / gcd,
+        }
+    }
+
+    fn is_negative(self) -> bool {
+        self.numerator < 0
+    }
+
+    fn is_zero(self) -> bool {
+        self.numerator == 0
+    }
+
+    fn integer(self) -> Option<i128> {
+        (self.denominator == 1).then_some(self.numerator)
+    }
+
+    // The operations below cancel common factors first, and return `None` on overflow.
+    fn checked_add(self, other: Self) -> Option<Self> {
+        let gcd = self.denominator.gcd(&other.denominator);
+        let numerator = self
+            .numerator
+            .checked_mul(other.denominator / gcd)?
+            .checked_add(other.numerator.checked_mul(self.denominator / gcd)?)?;
+        let denominator = self.denominator.checked_mul(other.denominator / gcd)?;
+        Some(Self::new(numerator, denominator))
+    }
+
+    fn checked_sub(self, other: Self) -> Option<Self> {
+        self.checked_add(Self::new(-other.numerator, other.denominator))
+    }
+
+    fn checked_mul(self, other: Self) -> Option<Self> {
+        let first = self.numerator.gcd(&other.denominator);
+        let second = other.numerator.gcd(&self.denominator);
+        Some(Self::new(
+            (self.numerator / first).checked_mul(other.numerator / second)?,
+            (self.denominator / second).checked_mul(other.denominator / first)?,
+        ))
+    }
+
+    fn checked_div(self, other: Self) -> Option<Self> {
+        if other.is_zero() {
+            return None;
+        }
+        self.checked_mul(Self::new(other.denominator, other.numerator))
+    }
+}
+
+impl From<Value> for Rational {
+    fn from(value: Value) -> Self {
+        Self::new(value.into(), 1)
+    }
+}
+
+impl PartialOrd for Rational {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Rational {
+    // Cross-multiplying can overflow, in which case the integer parts are compared, then the
+    // inverses of what remains (i.e. the continued fractions).
+    fn cmp(&self, other: &Self) -> Ordering {
+        if let (Some(first), Some(second)) = (
+            self.numerator.checked_mul(other.denominator),
+            other.numerator.checked_mul(self.denominator),
+        ) {
+            return first.cmp(&second);
+        }
+
+        let integers = (
+            self.numerator.div_euclid(self.denominator),
+            other.numerator.div_euclid(other.denominator),
+        );
+        let remainders = (
+            self.numerator.rem_euclid(self.denominator),
+            other.numerator.rem_euclid(other.denominator),
+        );
+        integers.0.cmp(&integers.1).then_with(|| match remainders {
+            (0, 0) => Ordering::Equal,
+            (0, _) => Ordering::Less,
+            (_, 0) => Ordering::Greater,
+            (first, second) => Rational::new(other.denominator, second)
+                .cmp(&Rational::new(self.denominator, first)),
+        })
+    }
+}
+
+impl Display for Rational {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        if self.denominator == 1 {
+            write!(f, "{}", self.numerator)
+        } else {
+            write!(f, "{}/{}", self.numerator, self.denominator)
+        }
+    }
+}
 
 #[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
 struct Position {
@@ -86,29 +204,151 @@ impl Hailstone {
         Self { position, velocity }
     }
 
-    fn crosses_path_xy(self, other: Self, test_area: &TestArea) -> bool {
-        // Convert them into lines.
-        let slope1 = (self.velocity.y as f64) / (self.velocity.x as f64);
-        let intercept1 = (self.position.y as f64) - slope1 * (self.position.x as f64);
-        let slope2 = (other.velocity.y as f64) / (other.velocity.x as f64);
-        let intercept2 = (other.position.y as f64) - slope2 * (other.position.x as f64);
+    // Where the hailstone is at the given time.
+    fn at(self, time: Rational) -> Option<[Rational; 3]> {
+        let (p, v) = (self.position, self.velocity);
+        let coordinate =
+            |p: Value, v: Value| Rational::from(v).checked_mul(time)?.checked_add(p.into());
+        Some([
+            coordinate(p.x, v.x)?,
+            coordinate(p.y, v.y)?,
+            coordinate(p.z, v.z)?,
+        ])
+    }
 
-        // Find the interception point.
-        let x = (intercept2 - intercept1) / (slope1 - slope2);
-        let y = x * slope1 + intercept1;
-        let min_coordinate = *test_area.start() as f64;
-        let max_coordinate = *test_area.end() as f64;
+    // The time at which two hailstones are at the same place, if they ever are.
+    fn collision(self, other: Self) -> Option<Rational> {
+        let dp = other.position - self.position;
+        let dv = self.velocity - other.velocity;
+
+        let mut time = None;
+        for (p, v) in [(dp.x, dv.x), (dp.y, dv.y), (dp.z, dv.z)] {
+            if v == 0 {
+                if p != 0 {
+                    return None;
+                }
+            } else {
+                let t = Rational::new(p.into(), v.into());
+                if time.is_some_and(|time| time != t) {
+                    return None;
+                }
+                time = Some(t);
+            }
+        }
+
+        // With the same velocity, they are either always or never together.
+        let time = time.unwrap_or(Rational::from(0));
+        (!time.is_negative()).then_some(time)
+    }
 
-        // Check that we are moving forward in time and that we are within the test area.
-        ((x - self.position.x as f64) / (self.velocity.x as f64)).is_sign_positive()
-            && ((x - other.position.x as f64) / (other.velocity.x as f64)).is_sign_positive()
-            && x >= min_coordinate
-            && x <= max_coordinate
-            && y >= min_coordinate
-            && y <= max_coordinate
+    // Where the paths of two hailstones cross, ignoring the z axis. Solves
+    // `p1 + t1 * v1 = p2 + t2 * v2` with Cramer's rule, in exact arithmetic.
+    fn crossing_xy(self, other: Self, test_area: &TestArea) -> Crossing {
+        let (p1, v1) = (self.position, self.velocity);
+        let (p2, v2) = (other.position, other.velocity);
+        let cross = |ax: Value, ay: Value, bx: Value, by: Value| {
+            i128::from(ax) * i128::from(by) - i128::from(ay) * i128::from(bx)
+        };
+
+        let area = Rational::from(*test_area.start())..=Rational::from(*test_area.end());
+        let classify = |intersection: Intersection| {
+            if intersection.times.0.is_negative() || intersection.times.1.is_negative() {
+                Crossing::Past(intersection)
+            } else if area.contains(&intersection.x) && area.contains(&intersection.y) {
+                Crossing::Inside(intersection)
+            } else {
+                Crossing::Outside(intersection)
+            }
+        };
+
+        // A hailstone which does not move (ignoring z) has a single point for a path, where it is
+        // from time 0 on.
+        let still = |v: Velocity| v.x == 0 && v.y == 0;
+        if still(v1) || still(v2) {
+            // When the hailstone gets to the target, if it ever does.
+            let time = |p: Position, v: Velocity, target: Position| {
+                let d = target - p;
+                if still(v) {
+                    return (d.x == 0 && d.y == 0).then_some(Rational::from(0));
+                }
+                (cross(d.x, d.y, v.x, v.y) == 0).then(|| {
+                    let (vx, vy) = (i128::from(v.x), i128::from(v.y));
+                    Rational::new(
+                        i128::from(d.x) * vx + i128::from(d.y) * vy,
+                        vx * vx + vy * vy,
+                    )
+                })
+            };
+            let (point, times) = if still(v1) {
+                (p1, time(p2, v2, p1).map(|t| (Rational::from(0), t)))
+            } else {
+                (p2, time(p1, v1, p2).map(|t| (t, Rational::from(0))))
+            };
+
+            return match times {
+                Some(times) => classify(Intersection {
+                    x: point.x.into(),
+                    y: point.y.into(),
+                    times,
+                }),
+                None => Crossing::Missed,
+            };
+        }
+
+        let dp = p2 - p1;
+        let determinant = cross(v1.x, v1.y, v2.x, v2.y);
+        if determinant == 0 {
+            // Either on the same line, or never meeting.
+            return if cross(dp.x, dp.y, v1.x, v1.y) == 0 {
+                Crossing::Coincident
+            } else {
+                Crossing::Parallel
+            };
+        }
+
+        let t1 = cross(dp.x, dp.y, v2.x, v2.y);
+        let t2 = cross(dp.x, dp.y, v1.x, v1.y);
+        let intersection = Intersection {
+            x: Rational::new(
+                i128::from(p1.x) * determinant + t1 * i128::from(v1.x),
+                determinant,
+            ),
+            y: Rational::new(
+                i128::from(p1.y) * determinant + t1 * i128::from(v1.y),
+                determinant,
+            ),
+            times: (
+                Rational::new(t1, determinant),
+                Rational::new(t2, determinant),
+            ),
+        };
+
+        classify(intersection)
     }
 }
 
+// Where two paths meet, and when each hailstone gets there.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Intersection {
+    x: Rational,
+    y: Rational,
+    times: (Rational, Rational),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Crossing {
+    Inside(Intersection),
+    Outside(Intersection),
+    // At least one of the hailstones went through the intersection before time 0.
+    Past(Intersection),
+    Parallel,
+    // A hailstone which does not move (ignoring z) is away from the path of the other.
+    Missed,
+    // The paths are the same line. They share more than a single point, so they are not counted
+    // as crossing.
+    Coincident,
+}
+
 impl FromStr for Hailstone {
     type Err = ParseIntError;
 
@@ -131,11 +371,201 @@ fn part1(hailstones: &[Hailstone], test_area: &TestArea) -> usize {
                 .iter()
                 .map(move |second| (first, second))
         })
-        .filter(|(a, b)| a.crosses_path_xy(**b, test_area))
+        .filter(|(a, b)| matches!(a.crossing_xy(**b, test_area), Crossing::Inside(_)))
         .count()
 }
 
-fn part2(hailstones: &[Hailstone]) -> Value {
+// Returns `None` on overflow.
+fn cross(a: Vector, b: Vector) -> Option<Vector> {
+    let product = |i: usize, j: usize| a[i].checked_mul(b[j])?.checked_sub(a[j].checked_mul(b[i])?);
+    Some([product(1, 2)?, product(2, 0)?, product(0, 1)?])
+}
+
+// Bring the matrix to reduced row echelon form, returning the column of each pivot. Returns `None`
+// on overflow.
+fn eliminate(matrix: &mut [Vec<Rational>]) -> Option<Vec<usize>> {
+    let columns = matrix.first().map_or(0, Vec::len);
+    let mut pivots = Vec::new();
+
+    for column in 0..columns {
+        let row = pivots.len();
+        let Some(pivot) = (row..matrix.len()).find(|&idx| !matrix[idx][column].is_zero()) else {
+            continue;
+        };
+        matrix.swap(row, pivot);
+
+        let divisor = matrix[row][column];
+        for value in matrix[row].iter_mut() {
+            *value = value.checked_div(divisor)?;
+        }
+        let pivot_row = matrix[row].clone();
+        for (other, values) in matrix.iter_mut().enumerate() {
+            let factor = values[column];
+            if other == row || factor.is_zero() {
+                continue;
+            }
+            for (value, pivot) in values.iter_mut().zip(&pivot_row) {
+                *value = value.checked_sub(pivot.checked_mul(factor)?)?;
+            }
+        }
+
+        pivots.push(column);
+    }
+
+    Some(pivots)
+}
+
+// The equations `P × v + p × V = p × v` on the rock position `P` and velocity `V`, one per axis,
+// as rows of coefficients followed by the constant.
+fn equations(position: Vector, velocity: Vector) -> Option<Vec<Vec<Rational>>> {
+    let constant = cross(position, velocity)?;
+    Some(
+        (0..3)
+            .map(|axis| {
+                let (i, j) = ((axis + 1) % 3, (axis + 2) % 3);
+                let mut row = [0; 7];
+                row[i] = velocity[j];
+                row[j] = -velocity[i];
+                row[3 + j] = position[i];
+                row[3 + i] = -position[j];
+                row[6] = constant[axis];
+                row.map(|value| Rational::new(value, 1)).to_vec()
+            })
+            .collect(),
+    )
+}
+
+// Find the rock throw which hits every hailstone. For each hailstone, `(P - p) × (V - v) = 0`,
+// where the only non-linear term `P × V` is the same for all of them: the difference between two
+// hailstones is linear. Seen from the first hailstone, which then stays at the origin, each other
+// hailstone gives `P × v + p × V = p × v`, and two of them are enough for the six unknowns, solved
+// by Gaussian elimination. Working relative to a hailstone keeps the numbers small enough for
+// exact arithmetic.
+fn throw(hailstones: &[Hailstone]) -> Result<Hailstone, String> {
+    let (first, others) = hailstones.split_first().ok_or("No hailstones.")?;
+    let vectors = |hailstone: &Hailstone| {
+        let (p, v) = (hailstone.position, hailstone.velocity);
+        (
+            [p.x, p.y, p.z].map(i128::from),
+            [v.x, v.y, v.z].map(i128::from),
+        )
+    };
+    let (p0, v0) = vectors(first);
+    let relative: Vec<(Vector, Vector)> = others
+        .iter()
+        .map(|hailstone| {
+            let (p, v) = vectors(hailstone);
+            (
+                [0, 1, 2].map(|axis| p[axis] - p0[axis]),
+                [0, 1, 2].map(|axis| v[axis] - v0[axis]),
+            )
+        })
+        .collect();
+
+    for (idx, &(p1, v1)) in relative.iter().enumerate() {
+        for &(p2, v2) in &relative[idx + 1..] {
+            let Some(mut matrix) = equations(p1, v1)
+                .zip(equations(p2, v2))
+                .map(|(first, second)| [first, second].concat())
+            else {
+                continue;
+            };
+            // Parallel paths, for instance, do not determine the rock.
+            if eliminate(&mut matrix).is_none_or(|pivots| pivots != [0, 1, 2, 3, 4, 5]) {
+                continue;
+            }
+
+            let unknowns: Vec<Value> = matrix
+                .iter()
+                .zip(p0.iter().chain(&v0))
+                .map(|(row, origin)| Value::try_from(row[6].integer()?.checked_add(*origin)?).ok())
+                .collect::<Option<_>>()
+                .ok_or("There is no integer solution.")?;
+            let rock = Hailstone::new(
+                Position::new(unknowns[0], unknowns[1], unknowns[2]),
+                Velocity::new(unknowns[3], unknowns[4], unknowns[5]),
+            );
+
+            if hailstones
+                .iter()
+                .any(|&hailstone| rock.collision(hailstone).is_none())
+            {
+                return Err("The rock cannot hit every hailstone.".to_string());
+            }
+            return Ok(rock);
+        }
+    }
+
+    Err("The hailstones do not determine a single throw.".to_string())
+}
+
+// A hailstone hit by the rock, when and where.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Impact {
+    hailstone: usize,
+    time: Rational,
+    position: [Rational; 3],
+}
+
+impl Display for Impact {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let [x, y, z] = self.position;
+        write!(
+            f,
+            "{}: hailstone {} at {x}, {y}, {z}",
+            self.time, self.hailstone
+        )
+    }
+}
+
+// Every hailstone hit by the rock, in chronological order.
+fn impacts(rock: Hailstone, hailstones: &[Hailstone]) -> Result<Vec<Impact>, String> {
+    let mut impacts = hailstones
+        .iter()
+        .enumerate()
+        .map(|(hailstone, &other)| {
+            let time = rock
+                .collision(other)
+                .ok_or(format!("The rock misses hailstone {hailstone}."))?;
+            let position = rock
+                .at(time)
+                .ok_or(format!("Hailstone {hailstone} is too far away."))?;
+            Ok(Impact {
+                hailstone,
+                time,
+                position,
+            })
+        })
+        .collect::<Result<Vec<_>, String>>()?;
+
+    impacts.sort_by_key(|impact| (impact.time, impact.hailstone));
+    Ok(impacts)
+}
+
+// The pairs of hailstones which actually hit each other, with the time they do.
+fn collisions(hailstones: &[Hailstone]) -> Vec<(usize, usize, Rational)> {
+    hailstones
+        .iter()
+        .enumerate()
+        .flat_map(|(idx, first)| {
+            hailstones[idx + 1..]
+                .iter()
+                .enumerate()
+                .filter_map(move |(offset, second)| {
+                    let time = first.collision(*second)?;
+                    Some((idx, idx + 1 + offset, time))
+                })
+        })
+        .collect()
+}
+
+fn part2(hailstones: &[Hailstone]) -> Result<Value, String> {
+    let rock = throw(hailstones)?;
+    Ok(rock.position.x + rock.position.y + rock.position.z)
+}
+
+#[cfg(feature = "z3")]
+fn part2_z3(hailstones: &[Hailstone]) -> Result<Value, String> {
     // Easier to use z3... :(
     let context = Context::new(&Config::new());
     let solver = Solver::new(&context);
@@ -149,7 +579,7 @@ fn part2(hailstones: &[Hailstone]) -> Value {
     let vz = Int::new_const(&context, "vz");
 
     // Fill in our constraints. We only need a few hailstones, not all of them.
-    for hailstone in &hailstones[..4] {
+    for hailstone in hailstones.iter().take(4) {
         let hailstone_px = Int::from_i64(&context, hailstone.position.x);
         let hailstone_py = Int::from_i64(&context, hailstone.position.y);
         let hailstone_pz = Int::from_i64(&context, hailstone.position.z);
@@ -170,12 +600,15 @@ fn part2(hailstones: &[Hailstone]) -> Value {
     }
 
     solver.check();
-    let model = solver.get_model().unwrap();
-    let px = model.get_const_interp(&px).unwrap().as_i64().unwrap();
-    let py = model.get_const_interp(&py).unwrap().as_i64().unwrap();
-    let pz = model.get_const_interp(&pz).unwrap().as_i64().unwrap();
-
-    px + py + pz
+    let model = solver.get_model().ok_or("No solution.")?;
+    let value = |unknown: &Int| {
+        model
+            .get_const_interp(unknown)
+            .and_then(|value| value.as_i64())
+            .ok_or("No integer solution.")
+    };
+
+    Ok(value(&px)? + value(&py)? + value(&pz)?)
 }
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
@@ -185,6 +618,32 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         .map(str::parse)
         .collect::<Result<Vec<Hailstone>, _>>()?;
 
+    // `report` lists when and where the rock hits each hailstone, and `collisions` lists the
+    // hailstones which hit each other.
+    match std::env::args().nth(1).as_deref() {
+        Some("report") => {
+            let rock = throw(&hailstones)?;
+            let Position { x, y, z } = rock.position;
+            let Velocity {
+                x: vx,
+                y: vy,
+                z: vz,
+            } = rock.velocity;
+            println!("The rock is thrown from {x}, {y}, {z} at {vx}, {vy}, {vz}.");
+            for impact in impacts(rock, &hailstones)? {
+                println!("{impact}");
+            }
+            return Ok(());
+        }
+        Some("collisions") => {
+            for (first, second, time) in collisions(&hailstones) {
+                println!("{time}: hailstones {first} and {second}");
+            }
+            return Ok(());
+        }
+        _ => {}
+    }
+
     println!(
         "The first answer is: {}",
         part1(
@@ -192,7 +651,14 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
             &RangeInclusive::new(200000000000000, 400000000000000)
         )
     );
-    println!("The second answer is: {}", part2(&hailstones));
+    // `z3` solves the second part with the z3 solver instead, when built with the `z3` feature.
+    #[cfg(feature = "z3")]
+    if std::env::args().nth(1).as_deref() == Some("z3") {
+        println!("The second answer is: {}", part2_z3(&hailstones)?);
+        return Ok(());
+    }
+
+    println!("The second answer is: {}", part2(&hailstones)?);
 
     Ok(())
 }
@@ -224,6 +690,162 @@ mod tests {
         assert_eq!(expected, actual);
     }
 
+    #[test]
+    fn test_crossing_xy() {
+        let hailstones = EXAMPLE
+            .lines()
+            .map(str::parse)
+            .collect::<Result<Vec<Hailstone>, _>>()
+            .unwrap();
+
+        // Hailstones A and B cross inside the test area, at x=14.333, y=15.333.
+        let Crossing::Inside(intersection) = hailstones[0].crossing_xy(hailstones[1], &TEST_AREA)
+        else {
+            panic!("A and B should cross inside the test area.");
+        };
+        assert_eq!(Rational::new(43, 3), intersection.x);
+        assert_eq!(Rational::new(46, 3), intersection.y);
+        assert_eq!(
+            (Rational::new(7, 3), Rational::new(11, 3)),
+            intersection.times
+        );
+
+        assert!(matches!(
+            hailstones[0].crossing_xy(hailstones[3], &TEST_AREA),
+            Crossing::Outside(_)
+        ));
+        assert!(matches!(
+            hailstones[0].crossing_xy(hailstones[4], &TEST_AREA),
+            Crossing::Past(_)
+        ));
+        assert_eq!(
+            Crossing::Parallel,
+            hailstones[1].crossing_xy(hailstones[2], &TEST_AREA)
+        );
+
+        // Vertical and coincident paths, which slopes cannot handle.
+        let vertical: Hailstone = "10, 0, 0 @ 0, 1, 0".parse().unwrap();
+        let horizontal: Hailstone = "0, 10, 0 @ 2, 0, 0".parse().unwrap();
+        let Crossing::Inside(intersection) = vertical.crossing_xy(horizontal, &TEST_AREA) else {
+            panic!("The paths should cross at 10, 10.");
+        };
+        assert_eq!(
+            (Rational::from(10), Rational::from(10)),
+            (intersection.x, intersection.y)
+        );
+        let behind: Hailstone = "10, -5, 3 @ 0, 3, 1".parse().unwrap();
+        assert_eq!(
+            Crossing::Coincident,
+            vertical.crossing_xy(behind, &TEST_AREA)
+        );
+
+        // A hailstone which only moves along z stays at a single point, which the other reaches
+        // or not.
+        let still: Hailstone = "10, 10, 0 @ 0, 0, 1".parse().unwrap();
+        let Crossing::Inside(intersection) = still.crossing_xy(horizontal, &TEST_AREA) else {
+            panic!("The moving hailstone should reach 10, 10.");
+        };
+        assert_eq!((Rational::from(0), Rational::from(5)), intersection.times);
+        let Crossing::Inside(intersection) = horizontal.crossing_xy(still, &TEST_AREA) else {
+            panic!("The moving hailstone should reach 10, 10.");
+        };
+        assert_eq!((Rational::from(5), Rational::from(0)), intersection.times);
+        let passed: Hailstone = "20, 10, 0 @ 1, 0, 0".parse().unwrap();
+        assert!(matches!(
+            still.crossing_xy(passed, &TEST_AREA),
+            Crossing::Past(_)
+        ));
+        let elsewhere: Hailstone = "0, 20, 0 @ 1, 0, 0".parse().unwrap();
+        assert_eq!(Crossing::Missed, still.crossing_xy(elsewhere, &TEST_AREA));
+        assert!(matches!(
+            still.crossing_xy(still, &TEST_AREA),
+            Crossing::Inside(_)
+        ));
+    }
+
+    #[test]
+    fn test_rational_order() {
+        let half = Rational::new(1, 2);
+        assert!(Rational::new(-1, 3) < half);
+        assert!(half < Rational::new(2, 3));
+
+        // Cross-multiplying these overflows.
+        let big = Rational::new(i128::MAX, 3);
+        let bigger = Rational::new(i128::MAX - 1, 2);
+        assert!(big < bigger);
+        assert!(Rational::new(-i128::MAX, 2) < Rational::new(-i128::MAX + 1, 3));
+        assert_eq!(Ordering::Equal, big.cmp(&big));
+        assert!(Rational::new(i128::MAX, 7) < Rational::new(i128::MAX - 2, 6));
+    }
+
+    #[test]
+    fn test_throw() {
+        let hailstones = EXAMPLE
+            .lines()
+            .map(str::parse)
+            .collect::<Result<Vec<Hailstone>, _>>()
+            .unwrap();
+        let expected: Hailstone = "24, 13, 10 @ -3, 1, 2".parse().unwrap();
+        assert_eq!(Ok(expected), throw(&hailstones));
+
+        // Input-sized numbers, thrown from 271828182845904, 314159265358979, 161803398874989 at
+        // -157, 228, 91.
+        let hailstones: Vec<Hailstone> = [
+            "193899778769373, 292877612840087, 225961321909884 @ 92, 296, -114",
+            "225578306302784, 520549339432652, 273381226035266 @ -77, -129, -102",
+            "247791133216284, 662696484988469, 351696090948987 @ -127, -207, -146",
+            "132823163147457, 801149640492994, 105066656140929 @ -10, -287, 151",
+        ]
+        .iter()
+        .map(|line| line.parse().unwrap())
+        .collect();
+        let rock = throw(&hailstones).unwrap();
+        assert_eq!(
+            Position::new(271828182845904, 314159265358979, 161803398874989),
+            rock.position
+        );
+
+        // Moving the last hailstone slightly makes it impossible, and so does moving the first one,
+        // which the others are seen from.
+        let mut moved = hailstones.clone();
+        moved[3].position.x += 1;
+        assert!(throw(&moved).is_err());
+        let mut moved = hailstones;
+        moved[0].position.y += 7;
+        assert!(throw(&moved).is_err());
+    }
+
+    #[test]
+    fn test_impacts() {
+        let mut hailstones = EXAMPLE
+            .lines()
+            .map(str::parse)
+            .collect::<Result<Vec<Hailstone>, _>>()
+            .unwrap();
+        let rock = throw(&hailstones).unwrap();
+
+        let report: Vec<String> = impacts(rock, &hailstones)
+            .unwrap()
+            .iter()
+            .map(Impact::to_string)
+            .collect();
+        let expected = [
+            "1: hailstone 4 at 21, 14, 12",
+            "3: hailstone 1 at 15, 16, 16",
+            "4: hailstone 2 at 12, 17, 18",
+            "5: hailstone 0 at 9, 18, 20",
+            "6: hailstone 3 at 6, 19, 22",
+        ];
+        assert_eq!(expected.to_vec(), report);
+
+        // None of them collide, until one is sent to meet A at 15, 15, 26, and another sits where
+        // B was in the past.
+        assert!(collisions(&hailstones).is_empty());
+        hailstones.push("13, 15, 26 @ 1, 0, 0".parse().unwrap());
+        hailstones.push("19, 20, 24 @ 0, 0, 0".parse().unwrap());
+        assert_eq!(vec![(0, 5, Rational::from(2))], collisions(&hailstones));
+    }
+
     #[test]
     fn test_part2() {
         let hailstones = EXAMPLE
@@ -231,7 +853,7 @@ mod tests {
             .map(str::parse)
             .collect::<Result<Vec<Hailstone>, _>>()
             .unwrap();
-        let actual = part2(&hailstones);
+        let actual = part2(&hailstones).unwrap();
         let expected = 47;
 
         assert_eq!(expected, actual);
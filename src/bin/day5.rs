@@ -1,6 +1,6 @@
 use advent_of_code_2023::range_extension::RangeExtension;
 use rangemap::RangeSet;
-use std::collections::BTreeMap;
+use std::collections::HashSet;
 use std::num::ParseIntError;
 use std::ops::Range;
 use std::str::FromStr;
@@ -11,20 +11,32 @@ type Id = i64;
 
 #[derive(Debug, Default, Clone)]
 struct Map {
+    source: String,
+    destination: String,
     conversions: Vec<(Range<Id>, Id)>,
 }
 
 impl FromStr for Map {
-    type Err = ParseIntError;
+    type Err = String;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let mut conversions = Vec::default();
+        let mut lines = s.lines();
+        let header = lines.next().unwrap_or_default();
+        let (source, destination) = header
+            .strip_suffix(" map:")
+            .and_then(|categories| categories.split_once("-to-"))
+            .ok_or_else(|| format!("Invalid header: {header}."))?;
 
-        for line in s.lines().skip(1) {
-            let mut iter = line.split_whitespace();
-            let destination: Id = iter.next().unwrap_or_default().parse()?;
-            let source: Id = iter.next().unwrap_or_default().parse()?;
-            let range: Id = iter.next().unwrap_or_default().parse()?;
+        let mut conversions = Vec::default();
+        for line in lines {
+            let numbers = line
+                .split_whitespace()
+                .map(Id::from_str)
+                .collect::<Result<Vec<Id>, ParseIntError>>()
+                .map_err(|e| format!("Invalid line: {line} ({e})."))?;
+            let [destination, source, range] = numbers[..] else {
+                return Err(format!("Invalid line: {line}."));
+            };
 
             let offset = destination - source;
             conversions.push((
@@ -36,71 +48,308 @@ impl FromStr for Map {
             ));
         }
 
-        Ok(Self { conversions })
+        Ok(Self {
+            source: source.to_string(),
+            destination: destination.to_string(),
+            conversions,
+        })
     }
 }
 
-fn parse_input(input: &str) -> (Vec<Id>, Vec<Map>) {
-    let mut sections = input.split("\n\n");
+// Ids are never negative, which leaves room for the offsets.
+const IDS: Range<Id> = 0..Id::MAX;
 
-    let seeds = sections
-        .next()
-        .expect("Bad format.")
-        .split_whitespace()
-        .filter_map(|substring| substring.parse().ok())
-        .collect();
+// A function over all the ids, made of sorted and contiguous pieces, each adding its offset.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct PiecewiseMap {
+    pieces: Vec<(Range<Id>, Id)>,
+}
 
-    let maps = sections
-        .map(str::parse)
-        .collect::<Result<Vec<Map>, _>>()
-        .expect("Bad format.");
-    (seeds, maps)
+impl Default for PiecewiseMap {
+    fn default() -> Self {
+        Self {
+            pieces: vec![(IDS, 0)],
+        }
+    }
 }
 
-fn part1(seeds: &[Id], maps: &[Map]) -> Id {
-    let mut numbers: BTreeMap<Id, Id> = seeds.iter().map(|&n| (n, n)).collect();
+impl From<&Map> for PiecewiseMap {
+    fn from(map: &Map) -> Self {
+        let mut conversions = map.conversions.clone();
+        conversions.sort_unstable_by_key(|(range, _)| range.start);
 
-    for map in maps {
-        for (range, offset) in &map.conversions {
-            for (_, destination) in numbers.range_mut(range.clone()) {
-                *destination += offset;
+        // Ids which are not converted keep their number.
+        let mut pieces = Vec::new();
+        let mut start = IDS.start;
+        for (range, offset) in conversions {
+            if start < range.start {
+                pieces.push((start..range.start, 0));
             }
+            start = range.end;
+            pieces.push((range, offset));
         }
+        pieces.push((start..IDS.end, 0));
 
-        numbers = numbers.into_values().map(|n| (n, n)).collect();
+        Self { pieces }
     }
+}
 
-    numbers.into_keys().min().unwrap_or_default()
+impl PiecewiseMap {
+    // The index of the piece containing the id.
+    fn piece(&self, id: Id) -> usize {
+        self.pieces.partition_point(|(range, _)| range.end <= id)
+    }
+
+    fn overlapping<'a>(
+        &'a self,
+        range: &'a Range<Id>,
+    ) -> impl Iterator<Item = (Range<Id>, Id)> + 'a {
+        self.pieces[self.piece(range.start)..]
+            .iter()
+            .take_while(|(piece, _)| piece.start < range.end)
+            .map(|(piece, offset)| (piece.intersection(range), *offset))
+    }
+
+    fn apply(&self, id: Id) -> Id {
+        id + self.pieces[self.piece(id)].1
+    }
+
+    fn apply_ranges(&self, ranges: &RangeSet<Id>) -> RangeSet<Id> {
+        ranges
+            .iter()
+            .flat_map(|range| self.overlapping(range))
+            .map(|(common, offset)| common.start + offset..common.end + offset)
+            .collect()
+    }
+
+    // All the ids which map into the ranges.
+    fn preimage(&self, ranges: &RangeSet<Id>) -> RangeSet<Id> {
+        let mut preimage = RangeSet::new();
+        for (piece, offset) in &self.pieces {
+            let image = piece.start + offset..piece.end + offset;
+            for range in ranges.overlapping(&image) {
+                let common = range.intersection(&image);
+                preimage.insert(common.start - offset..common.end - offset);
+            }
+        }
+
+        preimage
+    }
+
+    // The lowest id the ranges map to.
+    fn minimum(&self, ranges: &RangeSet<Id>) -> Option<Id> {
+        self.apply_ranges(ranges)
+            .iter()
+            .next()
+            .map(|range| range.start)
+    }
+
+    // Apply `self`, then `other`.
+    fn then(&self, other: &Self) -> Self {
+        let mut pieces: Vec<(Range<Id>, Id)> = Vec::new();
+        for (piece, offset) in &self.pieces {
+            let image = piece.start + offset..piece.end + offset;
+            for (common, other_offset) in other.overlapping(&image) {
+                let range = common.start - offset..common.end - offset;
+                let total = offset + other_offset;
+
+                // Merge with the previous piece when possible.
+                match pieces.last_mut() {
+                    Some((last, last_offset))
+                        if last.end == range.start && *last_offset == total =>
+                    {
+                        last.end = range.end
+                    }
+                    _ => pieces.push((range, total)),
+                }
+            }
+        }
+
+        Self { pieces }
+    }
 }
 
-fn part2(seeds: &[Id], maps: &[Map]) -> Id {
-    let mut sources: RangeSet<Id> = seeds
-        .chunks_exact(2)
-        .map(|pair| pair[0]..pair[0] + pair[1])
-        .collect();
+// The maps between categories (e.g. seed, soil, ..., location), which must not loop back.
+#[derive(Debug, Default, Clone)]
+struct Almanac {
+    maps: Vec<Map>,
+}
+
+impl Almanac {
+    fn new(maps: Vec<Map>) -> Result<Self, String> {
+        for (idx, map) in maps.iter().enumerate() {
+            if maps[..idx]
+                .iter()
+                .any(|other| other.source == map.source && other.destination == map.destination)
+            {
+                return Err(format!(
+                    "Duplicate map from '{}' to '{}'.",
+                    map.source, map.destination
+                ));
+            }
+        }
 
-    for map in maps {
-        let mut destinations = RangeSet::default();
-        for (range, offset) in &map.conversions {
-            for overlapping in sources.overlapping(range) {
-                let common = range.intersection(overlapping);
-                destinations.insert(common.start + offset..common.end + offset);
+        let almanac = Self { maps };
+        let (mut done, mut ongoing) = (HashSet::new(), HashSet::new());
+        for map in &almanac.maps {
+            if let Some(map) = almanac.find_loop(&map.source, &mut done, &mut ongoing) {
+                return Err(format!(
+                    "The maps loop back from '{}' to '{}'.",
+                    map.source, map.destination
+                ));
             }
-            sources.remove(range.clone());
         }
 
-        sources.extend(destinations);
+        Ok(almanac)
     }
 
-    sources.iter().next().expect("No seeds.").start
+    // Depth-first search for a map going back to a category still being explored.
+    fn find_loop<'a>(
+        &'a self,
+        category: &'a str,
+        done: &mut HashSet<&'a str>,
+        ongoing: &mut HashSet<&'a str>,
+    ) -> Option<&'a Map> {
+        if done.contains(category) {
+            return None;
+        }
+
+        ongoing.insert(category);
+        for map in self.maps.iter().filter(|map| map.source == category) {
+            if ongoing.contains(map.destination.as_str()) {
+                return Some(map);
+            }
+            if let Some(map) = self.find_loop(&map.destination, done, ongoing) {
+                return Some(map);
+            }
+        }
+        ongoing.remove(category);
+        done.insert(category);
+
+        None
+    }
+
+    // Every chain of maps from one category to the other.
+    fn paths(&self, source: &str, target: &str) -> Vec<Vec<&Map>> {
+        if source == target {
+            return vec![Vec::new()];
+        }
+
+        self.maps
+            .iter()
+            .filter(|map| map.source == source)
+            .flat_map(|map| {
+                self.paths(&map.destination, target)
+                    .into_iter()
+                    .map(move |mut path| {
+                        path.insert(0, map);
+                        path
+                    })
+            })
+            .collect()
+    }
+
+    // The composed maps from one category to the other.
+    fn chain(&self, source: &str, target: &str) -> Result<Option<PiecewiseMap>, String> {
+        match &self.paths(source, target)[..] {
+            [] => Ok(None),
+            [path] => Ok(Some(
+                path.iter()
+                    .fold(PiecewiseMap::default(), |acc, &map| acc.then(&map.into())),
+            )),
+            _ => Err(format!(
+                "There are several ways from '{source}' to '{target}'."
+            )),
+        }
+    }
+
+    // Map ranges from one category to another, going backwards through the maps if needed (e.g.
+    // to find which seeds end up in some locations).
+    fn convert(
+        &self,
+        source: &str,
+        target: &str,
+        ranges: &RangeSet<Id>,
+    ) -> Result<RangeSet<Id>, String> {
+        if let Some(chain) = self.chain(source, target)? {
+            Ok(chain.apply_ranges(ranges))
+        } else if let Some(chain) = self.chain(target, source)? {
+            Ok(chain.preimage(ranges))
+        } else {
+            Err(format!(
+                "There are no maps between '{source}' and '{target}'."
+            ))
+        }
+    }
+
+    fn seed_to_location(&self) -> Result<PiecewiseMap, String> {
+        self.chain("seed", "location")?
+            .ok_or_else(|| "There are no maps from 'seed' to 'location'.".to_string())
+    }
+}
+
+fn parse_input(input: &str) -> Result<(Vec<Id>, Almanac), String> {
+    let mut sections = input.split("\n\n");
+
+    let seeds = sections
+        .next()
+        .and_then(|seeds| seeds.strip_prefix("seeds:"))
+        .ok_or("Missing seeds.")?
+        .split_whitespace()
+        .map(Id::from_str)
+        .collect::<Result<Vec<Id>, _>>()
+        .map_err(|e| format!("Invalid seeds ({e})."))?;
+
+    let maps = sections.map(str::parse).collect::<Result<Vec<Map>, _>>()?;
+    let almanac = Almanac::new(maps)?;
+    almanac.seed_to_location()?;
+
+    Ok((seeds, almanac))
+}
+
+fn part1(seeds: &[Id], almanac: &Almanac) -> Result<Id, String> {
+    let seed_to_location = almanac.seed_to_location()?;
+    seeds
+        .iter()
+        .map(|&seed| seed_to_location.apply(seed))
+        .min()
+        .ok_or_else(|| "No seeds.".to_string())
+}
+
+fn part2(seeds: &[Id], almanac: &Almanac) -> Result<Id, String> {
+    let seeds: RangeSet<Id> = seeds
+        .chunks_exact(2)
+        .map(|pair| pair[0]..pair[0] + pair[1])
+        .collect();
+
+    almanac
+        .seed_to_location()?
+        .minimum(&seeds)
+        .ok_or_else(|| "No seeds.".to_string())
 }
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
     let input = std::fs::read_to_string(INPUT)?;
-    let (seeds, maps) = parse_input(&input);
+    let (seeds, almanac) = parse_input(&input)?;
+
+    // `convert <source> <target> <start> <end>` maps a range of ids from one category to another,
+    // e.g. `convert location seed 0 100` lists the seeds which end up in the first locations.
+    let mut args = std::env::args().skip(1);
+    if args.next().as_deref() == Some("convert") {
+        let source = args.next().ok_or("Missing source category.")?;
+        let target = args.next().ok_or("Missing target category.")?;
+        let start = args.next().ok_or("Missing start.")?.parse()?;
+        let end: Id = args.next().ok_or("Missing end.")?.parse()?;
+        let mut ranges = RangeSet::new();
+        ranges.insert(start..end);
+        for range in almanac.convert(&source, &target, &ranges)?.iter() {
+            println!("{}..{}", range.start, range.end);
+        }
+        return Ok(());
+    }
 
-    println!("The first answer is: {}", part1(&seeds, &maps));
-    println!("The second answer is: {}", part2(&seeds, &maps));
+    println!("The first answer is: {}", part1(&seeds, &almanac)?);
+    println!("The second answer is: {}", part2(&seeds, &almanac)?);
 
     Ok(())
 }
@@ -147,17 +396,76 @@ mod tests {
 
     #[test]
     fn test_part1() {
-        let (seeds, maps) = parse_input(EXAMPLE);
-        let actual = part1(&seeds, &maps);
+        let (seeds, almanac) = parse_input(EXAMPLE).unwrap();
+        let actual = part1(&seeds, &almanac).unwrap();
         let expected = 35;
 
         assert_eq!(expected, actual);
     }
 
+    #[test]
+    fn test_piecewise_map() {
+        let (_, almanac) = parse_input(EXAMPLE).unwrap();
+        let almanac = almanac.seed_to_location().unwrap();
+        let locations: Vec<Id> = [79, 14, 55, 13]
+            .into_iter()
+            .map(|seed| almanac.apply(seed))
+            .collect();
+        assert_eq!(vec![82, 43, 86, 35], locations);
+
+        let mut seeds = RangeSet::new();
+        seeds.insert(79..93);
+        let expected: RangeSet<Id> = (79..93)
+            .map(|seed| almanac.apply(seed))
+            .map(|l| l..l + 1)
+            .collect();
+        assert_eq!(expected, almanac.apply_ranges(&seeds));
+
+        let mut locations = RangeSet::new();
+        locations.insert(46..47);
+        let preimage = almanac.preimage(&locations);
+        assert!(preimage.contains(&82));
+        assert!(preimage
+            .iter()
+            .flat_map(Range::clone)
+            .all(|seed| almanac.apply(seed) == 46));
+    }
+
+    #[test]
+    fn test_categories() {
+        let (_, almanac) = parse_input(EXAMPLE).unwrap();
+
+        // Seed 79 has soil 81, fertilizer 81, water 81, light 74, temperature 78, humidity 78.
+        let mut humidity = RangeSet::new();
+        humidity.insert(78..79);
+        let soil = almanac.convert("humidity", "soil", &humidity).unwrap();
+        assert!(soil.contains(&81));
+        let seeds = almanac.convert("humidity", "seed", &humidity).unwrap();
+        assert!(seeds.contains(&79));
+
+        let mut locations = RangeSet::new();
+        locations.insert(46..47);
+        let seeds = almanac.convert("location", "seed", &locations).unwrap();
+        assert!(seeds.contains(&82));
+
+        // Without the water-to-light map, there is a missing link.
+        let (start, end) = (
+            EXAMPLE.find("water-to-light").unwrap(),
+            EXAMPLE.find("light-to-").unwrap(),
+        );
+        let missing = format!("{}{}", &EXAMPLE[..start], &EXAMPLE[end..]);
+        assert!(parse_input(&missing).is_err());
+        assert!(parse_input(&EXAMPLE.replace("soil-to-fertilizer", "soil-to-seed")).is_err());
+        assert!(
+            parse_input(&EXAMPLE.replace("humidity-to-location", "humidity-to-temperature"))
+                .is_err()
+        );
+    }
+
     #[test]
     fn test_part2() {
-        let (seeds, maps) = parse_input(EXAMPLE);
-        let actual = part2(&seeds, &maps);
+        let (seeds, almanac) = parse_input(EXAMPLE).unwrap();
+        let actual = part2(&seeds, &almanac).unwrap();
         let expected = 46;
 
         assert_eq!(expected, actual);
@@ -1,109 +1,262 @@
-use std::cmp::Ordering;
-use std::str::FromStr;
+use std::cmp::{Ordering, Reverse};
+use std::fmt::{Display, Formatter};
+use std::iter::repeat_n;
 
 const INPUT: &str = "./input/day7.txt";
 const HAND_SIZE: usize = 5;
 
-#[repr(usize)]
-#[derive(Debug, Clone, Copy, PartialOrd, Ord, PartialEq, Eq, Hash)]
-enum Card {
-    Joker = 0,
-    Two = 2,
-    Three = 3,
-    Four = 4,
-    Five = 5,
-    Six = 6,
-    Seven = 7,
-    Eight = 8,
-    Nine = 9,
-    Ten = 10,
-    Jack = 11,
-    Queen = 12,
-    King = 13,
-    Ace = 14,
-}
-
-impl TryFrom<char> for Card {
-    type Error = char;
-
-    fn try_from(value: char) -> Result<Self, Self::Error> {
-        match value {
-            '2' => Ok(Card::Two),
-            '3' => Ok(Card::Three),
-            '4' => Ok(Card::Four),
-            '5' => Ok(Card::Five),
-            '6' => Ok(Card::Six),
-            '7' => Ok(Card::Seven),
-            '8' => Ok(Card::Eight),
-            '9' => Ok(Card::Nine),
-            'T' => Ok(Card::Ten),
-            'J' => Ok(Card::Jack),
-            'Q' => Ok(Card::Queen),
-            'K' => Ok(Card::King),
-            'A' => Ok(Card::Ace),
-            _ => Err(value),
+// The rules of a game of Camel Cards.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Ruleset {
+    // The card labels, from weakest to strongest.
+    cards: Vec<char>,
+    // The cards which stand for whatever makes the hand strongest.
+    wildcards: Vec<char>,
+    hand_size: usize,
+    // The sizes of the groups of identical cards (largest first) for each hand type, from weakest
+    // to strongest. E.g. a full house is `[3, 2]`.
+    hand_types: Vec<Vec<usize>>,
+}
+
+impl Ruleset {
+    // By default, the hand types are ranked by their largest group, then the next one, and so on,
+    // which gives the usual hierarchy with five cards. Otherwise, every way to group the cards
+    // must be ranked, from weakest to strongest.
+    fn new(
+        cards: &str,
+        wildcards: &str,
+        hand_size: usize,
+        hand_types: Option<Vec<Vec<usize>>>,
+    ) -> Result<Self, String> {
+        let cards: Vec<char> = cards.chars().collect();
+        let wildcards: Vec<char> = wildcards.chars().collect();
+
+        if let Some(card) = cards
+            .iter()
+            .enumerate()
+            .find_map(|(idx, card)| cards[..idx].contains(card).then_some(card))
+        {
+            return Err(format!("Duplicate card: {card}."));
         }
+        if let Some(card) = wildcards.iter().find(|card| !cards.contains(card)) {
+            return Err(format!("Unknown wildcard: {card}."));
+        }
+
+        let mut groupings = partitions(hand_size, hand_size);
+        groupings.sort_unstable();
+        let hand_types = match hand_types {
+            None => groupings,
+            Some(mut hand_types) => {
+                for hand_type in hand_types.iter_mut() {
+                    hand_type.sort_unstable_by(|a, b| b.cmp(a));
+                }
+                let mut sorted = hand_types.clone();
+                sorted.sort_unstable();
+                if sorted != groupings {
+                    return Err(format!(
+                        "The hand types must rank every way to group {hand_size} cards, once."
+                    ));
+                }
+                hand_types
+            }
+        };
+
+        Ok(Self {
+            cards,
+            wildcards,
+            hand_size,
+            hand_types,
+        })
+    }
+
+    fn standard() -> Self {
+        Self::new("23456789TJQKA", "", HAND_SIZE, None).expect("The standard rules are valid.")
     }
-}
 
-#[derive(Debug, Clone, Copy, PartialOrd, Ord, PartialEq, Eq, Hash)]
-enum HandType {
-    HighCard,
-    OnePair,
-    TwoPairs,
-    ThreeOfAKind,
-    FullHouse,
-    FourOfAKind,
-    FiveOfAKind,
-}
-
-impl From<&[Card; HAND_SIZE]> for HandType {
-    fn from(value: &[Card; HAND_SIZE]) -> Self {
-        // Consider the jokers separately.
-        let mut counter = [0; 15];
-        let mut jokers = 0;
-        for &card in value {
-            if card == Card::Joker {
-                jokers += 1;
-            } else {
-                counter[card as usize] += 1;
+    // Jacks become jokers, which are the weakest cards.
+    fn jokers() -> Self {
+        Self::new("J23456789TQKA", "J", HAND_SIZE, None).expect("The joker rules are valid.")
+    }
+
+    fn strength(&self, card: char) -> Option<usize> {
+        self.cards.iter().position(|&other| other == card)
+    }
+
+    // The groups of identical cards, largest (then strongest) first.
+    fn groups(&self, cards: &[char]) -> Vec<(char, usize)> {
+        let mut groups: Vec<(char, usize)> = Vec::new();
+        for &card in cards {
+            match groups.iter_mut().find(|(label, _)| *label == card) {
+                Some((_, size)) => *size += 1,
+                None => groups.push((card, 1)),
             }
         }
+        groups.sort_unstable_by_key(|&(card, size)| Reverse((size, self.strength(card))));
+        groups
+    }
+
+    fn hand_type(&self, cards: &[char]) -> HandType {
+        let groups: Vec<usize> = self
+            .groups(cards)
+            .into_iter()
+            .map(|(_, size)| size)
+            .collect();
+        let rank = self
+            .hand_types
+            .iter()
+            .position(|hand_type| hand_type == &groups)
+            .expect("Every grouping is a hand type.");
+        HandType { rank, groups }
+    }
+
+    // Replace the wildcards with the cards giving the strongest hand type. The other cards keep
+    // their groups, so a hand type can be reached if its groups, largest first, are each at least
+    // as large as the groups of the hand. The wildcards then make up the difference, and fill the
+    // extra groups with cards which are not in the hand.
+    fn substitute(&self, cards: &[char]) -> Vec<char> {
+        let natural: Vec<char> = cards
+            .iter()
+            .copied()
+            .filter(|card| !self.wildcards.contains(card))
+            .collect();
+        let groups = self.groups(&natural);
+        let fresh: Vec<char> = self
+            .cards
+            .iter()
+            .rev()
+            .filter(|card| !self.wildcards.contains(card) && !natural.contains(card))
+            .copied()
+            .collect();
+
+        for hand_type in self.hand_types.iter().rev() {
+            let fits = hand_type.len() >= groups.len()
+                && hand_type.len() - groups.len() <= fresh.len()
+                && groups
+                    .iter()
+                    .zip(hand_type)
+                    .all(|(&(_, size), &target)| size <= target);
+            if !fits {
+                continue;
+            }
+
+            let mut labels = groups
+                .iter()
+                .zip(hand_type)
+                .flat_map(|(&(card, size), &target)| repeat_n(card, target - size))
+                .chain(
+                    hand_type[groups.len()..]
+                        .iter()
+                        .zip(&fresh)
+                        .flat_map(|(&target, &card)| repeat_n(card, target)),
+                );
+            return cards
+                .iter()
+                .map(|&card| {
+                    if self.wildcards.contains(&card) {
+                        labels.next().unwrap_or(card)
+                    } else {
+                        card
+                    }
+                })
+                .collect();
+        }
+
+        cards.to_vec()
+    }
+
+    fn hand(&self, s: &str) -> Result<Hand, String> {
+        let (cards, bid) = s
+            .trim()
+            .split_once(' ')
+            .ok_or(format!("Invalid hand: {s}."))?;
+
+        let cards: Vec<char> = cards.chars().collect();
+        if cards.len() != self.hand_size {
+            return Err(format!("Invalid hand size: {}.", cards.len()));
+        }
+        let strengths = cards
+            .iter()
+            .map(|&card| self.strength(card).ok_or(format!("Invalid card: {card}.")))
+            .collect::<Result<Vec<usize>, _>>()?;
+
+        let substituted = self.substitute(&cards);
+        let hand_type = self.hand_type(&substituted);
+        let bid = bid
+            .trim()
+            .parse()
+            .map_err(|_| format!("Invalid bid: {bid}."))?;
+
+        Ok(Hand {
+            hand_type,
+            cards,
+            substituted,
+            strengths,
+            bid,
+        })
+    }
+}
+
+// Every way to split `n` cards into groups of at most `max` cards, largest groups first.
+fn partitions(n: usize, max: usize) -> Vec<Vec<usize>> {
+    if n == 0 {
+        return vec![Vec::new()];
+    }
+
+    (1..=n.min(max))
+        .flat_map(|first| {
+            partitions(n - first, first)
+                .into_iter()
+                .map(move |mut rest| {
+                    rest.insert(0, first);
+                    rest
+                })
+        })
+        .collect()
+}
 
-        counter.sort_unstable();
-        // We can add the jokers to the largest count, since three of a kind is better than
-        // two pairs, and so on.
-        counter[14] += jokers;
-
-        match counter {
-            [.., 5] => HandType::FiveOfAKind,
-            [.., 4] => HandType::FourOfAKind,
-            [.., 2, 3] => HandType::FullHouse,
-            [.., 3] => HandType::ThreeOfAKind,
-            [.., 2, 2] => HandType::TwoPairs,
-            [.., 2] => HandType::OnePair,
-            _ => HandType::HighCard,
+#[derive(Debug, Clone, PartialOrd, Ord, PartialEq, Eq, Hash)]
+struct HandType {
+    rank: usize,
+    groups: Vec<usize>,
+}
+
+impl Display for HandType {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self.groups[..] {
+            [1, 1, 1, 1, 1] => write!(f, "high card"),
+            [2, 1, 1, 1] => write!(f, "one pair"),
+            [2, 2, 1] => write!(f, "two pairs"),
+            [3, 1, 1] => write!(f, "three of a kind"),
+            [3, 2] => write!(f, "full house"),
+            [4, 1] => write!(f, "four of a kind"),
+            [n] => write!(f, "{n} of a kind"),
+            _ => {
+                let groups: Vec<String> = self.groups.iter().map(usize::to_string).collect();
+                write!(f, "{}", groups.join("+"))
+            }
         }
     }
 }
 
-#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 struct Hand {
     hand_type: HandType,
-    hand: [Card; HAND_SIZE],
+    cards: Vec<char>,
+    // The cards once the wildcards are replaced, which give the hand type.
+    substituted: Vec<char>,
+    strengths: Vec<usize>,
     bid: u32,
 }
 
-impl Hand {
-    fn jacks_to_jokers(mut self) -> Self {
-        for card in self.hand.iter_mut() {
-            if *card == Card::Jack {
-                *card = Card::Joker;
-            }
+impl Display for Hand {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let cards: String = self.cards.iter().collect();
+        let substituted: String = self.substituted.iter().collect();
+        if cards == substituted {
+            write!(f, "{cards}: {}", self.hand_type)
+        } else {
+            write!(f, "{cards} as {substituted}: {}", self.hand_type)
         }
-
-        self.hand_type = HandType::from(&self.hand);
-        self
     }
 }
 
@@ -117,58 +270,103 @@ impl Ord for Hand {
     fn cmp(&self, other: &Self) -> Ordering {
         self.hand_type
             .cmp(&other.hand_type)
-            .then(self.hand.cmp(&other.hand))
+            .then(self.strengths.cmp(&other.strengths))
     }
 }
 
-impl FromStr for Hand {
-    type Err = char;
+// The hands from weakest to strongest, a hand's rank being its position starting from 1.
+fn ranked(input: &str, ruleset: &Ruleset) -> Result<Vec<Hand>, String> {
+    let mut hands = input
+        .lines()
+        .map(|line| ruleset.hand(line))
+        .collect::<Result<Vec<Hand>, _>>()?;
+    hands.sort_unstable();
+    Ok(hands)
+}
 
-    fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let (cards, bid) = s.trim().split_once(' ').expect("Bad format.");
-        let mut hand = [Card::Ace; HAND_SIZE];
-        for (idx, card) in cards.char_indices() {
-            hand[idx] = Card::try_from(card)?;
-        }
+fn winnings(input: &str, ruleset: &Ruleset) -> Result<u32, String> {
+    Ok(ranked(input, ruleset)?
+        .into_iter()
+        .enumerate()
+        .map(|(idx, hand)| ((idx + 1) as u32) * hand.bid)
+        .sum())
+}
 
-        let hand_type = HandType::from(&hand);
-        let bid = bid.parse().expect("Not a number.");
+// Every ranked hand with what it is played as, its bid and what it wins, then the total.
+fn table(hands: &[Hand]) -> String {
+    let width = hands.first().map_or(0, |hand| hand.cards.len()).max(4);
+    let mut lines = vec![format!(
+        "{:>5}  {:<width$}  {:<width$}  {:<16}  {:>5}  {:>9}",
+        "Rank", "Hand", "As", "Type", "Bid", "Winnings"
+    )];
 
-        Ok(Hand {
-            hand_type,
-            hand,
-            bid,
-        })
+    let mut total = 0;
+    for (idx, hand) in hands.iter().enumerate() {
+        let rank = idx + 1;
+        let winnings = rank as u32 * hand.bid;
+        total += winnings;
+        lines.push(format!(
+            "{rank:>5}  {:<width$}  {:<width$}  {:<16}  {:>5}  {winnings:>9}",
+            hand.cards.iter().collect::<String>(),
+            hand.substituted.iter().collect::<String>(),
+            hand.hand_type.to_string(),
+            hand.bid,
+        ));
     }
-}
 
-fn part1(hands: &[Hand]) -> u32 {
-    let mut hands = hands.to_vec();
-    hands.sort_unstable();
+    // Line the total up with the winnings, at the end of the rows.
+    let padding = lines[0].len() - 9;
+    lines.push(format!("{:<padding$}{total:>9}", "Total"));
+    lines.join("\n")
+}
 
-    hands
-        .into_iter()
-        .enumerate()
-        .map(|(idx, hand)| ((idx + 1) as u32) * hand.bid)
-        .sum()
+fn part1(input: &str) -> Result<u32, String> {
+    winnings(input, &Ruleset::standard())
 }
 
-fn part2(hands: &[Hand]) -> u32 {
-    // Simply replace the jacks with jokers.
-    let hands: Vec<Hand> = hands.iter().map(|hand| hand.jacks_to_jokers()).collect();
-    part1(&hands)
+fn part2(input: &str) -> Result<u32, String> {
+    winnings(input, &Ruleset::jokers())
 }
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
     let input = std::fs::read_to_string(INPUT)?;
-    let hands = input
-        .lines()
-        .map(str::parse)
-        .collect::<Result<Vec<Hand>, _>>()
-        .expect("Bad format.");
 
-    println!("The first answer is: {}", part1(&hands));
-    println!("The second answer is: {}", part2(&hands));
+    // `rules <cards> <wildcards> [hand size] [hand types]` scores the hands with other rules, the
+    // cards and hand types going from weakest to strongest (e.g. `rules J23456789TQKA J` or
+    // `rules 23456789TJQKA "" 5 1+1+1+1+1,2+1+1+1,3+1+1,2+2+1,3+2,4+1,5`). `table` lists the
+    // ranked hands with the joker rules, or with the given rules.
+    let mut args = std::env::args().skip(1);
+    let command = args.next();
+    if let Some(command @ ("rules" | "table")) = command.as_deref() {
+        let ruleset = match args.next() {
+            Some(cards) => {
+                let wildcards = args.next().unwrap_or_default();
+                let hand_size = args.next().map_or(Ok(HAND_SIZE), |size| size.parse())?;
+                let hand_types = args
+                    .next()
+                    .map(|hand_types| {
+                        hand_types
+                            .split(',')
+                            .map(|hand_type| hand_type.split('+').map(str::parse).collect())
+                            .collect::<Result<Vec<Vec<usize>>, _>>()
+                    })
+                    .transpose()?;
+                Ruleset::new(&cards, &wildcards, hand_size, hand_types)?
+            }
+            None if command == "table" => Ruleset::jokers(),
+            None => return Err("Missing cards.".into()),
+        };
+
+        if command == "table" {
+            println!("{}", table(&ranked(&input, &ruleset)?));
+        } else {
+            println!("The total winnings are: {}", winnings(&input, &ruleset)?);
+        }
+        return Ok(());
+    }
+
+    println!("The first answer is: {}", part1(&input)?);
+    println!("The second answer is: {}", part2(&input)?);
 
     Ok(())
 }
@@ -187,26 +385,96 @@ mod tests {
 
     #[test]
     fn test_part1() {
-        let hands = EXAMPLE
-            .lines()
-            .map(str::parse)
-            .collect::<Result<Vec<Hand>, _>>()
-            .unwrap();
-        let actual = part1(&hands);
+        let actual = part1(EXAMPLE).unwrap();
         let expected = 6440;
 
         assert_eq!(expected, actual);
     }
 
     #[test]
-    fn test_part2() {
-        let hands = EXAMPLE
-            .lines()
-            .map(str::parse)
-            .collect::<Result<Vec<Hand>, _>>()
-            .unwrap();
+    fn test_ruleset() {
+        let ruleset = Ruleset::standard();
+        let hand_types: Vec<Vec<usize>> = vec![
+            vec![1, 1, 1, 1, 1],
+            vec![2, 1, 1, 1],
+            vec![2, 2, 1],
+            vec![3, 1, 1],
+            vec![3, 2],
+            vec![4, 1],
+            vec![5],
+        ];
+        assert_eq!(hand_types, ruleset.hand_types);
+        assert!(ruleset.hand("32T3K 765").unwrap() < ruleset.hand("KK677 28").unwrap());
+        assert!(ruleset.hand("32T3 765").is_err());
+        assert!(Ruleset::new("23456789TJQKA", "X", HAND_SIZE, None).is_err());
+
+        // Both jacks and twos are wild, and the best six-card hand type uses both.
+        let ruleset = Ruleset::new("2J3456789TQKA", "2J", 6, None).unwrap();
+        assert_eq!(ruleset.hand_types.len(), 11);
+        let hand = ruleset.hand("2J3KQK 10").unwrap();
+        assert_eq!(vec![4, 1, 1], hand.hand_type.groups);
+        assert!(hand > ruleset.hand("KKKQQ3 20").unwrap());
+        let hand = ruleset.hand("22JJ22 10").unwrap();
+        assert_eq!(vec![6], hand.hand_type.groups);
 
-        let actual = part2(&hands);
+        // Changing the rank of the wildcard only changes how ties are broken.
+        let high = Ruleset::new("23456789TQKAJ", "J", HAND_SIZE, None).unwrap();
+        assert_eq!(Ok(5905), winnings(EXAMPLE, &Ruleset::jokers()));
+        assert_eq!(Ok(5905), winnings(EXAMPLE, &high));
+
+        // With two pairs beating three of a kind, a joker goes to the single card.
+        let hand_types = [
+            vec![1, 1, 1, 1, 1],
+            vec![2, 1, 1, 1],
+            vec![3, 1, 1],
+            vec![2, 2, 1],
+            vec![3, 2],
+            vec![4, 1],
+            vec![5],
+        ];
+        let ruleset = Ruleset::new("J23456789TQKA", "J", HAND_SIZE, Some(hand_types.to_vec()));
+        let hand = ruleset.unwrap().hand("KKJ23 1").unwrap();
+        assert_eq!("KKJ23 as KK323: two pairs", hand.to_string());
+        let incomplete = Some(hand_types[1..].to_vec());
+        assert!(Ruleset::new("J23456789TQKA", "J", HAND_SIZE, incomplete).is_err());
+
+        // Many wildcards are no harder than one.
+        let ruleset = Ruleset::new("J23456789TQKA", "J", 12, None).unwrap();
+        let hand = ruleset.hand("JJJJJJJJJJJJ 1").unwrap();
+        assert_eq!(
+            "JJJJJJJJJJJJ as AAAAAAAAAAAA: 12 of a kind",
+            hand.to_string()
+        );
+    }
+
+    #[test]
+    fn test_table() {
+        let hands = ranked(EXAMPLE, &Ruleset::jokers()).unwrap();
+        let explained: Vec<String> = hands.iter().map(Hand::to_string).collect();
+        let expected = [
+            "32T3K: one pair",
+            "KK677: two pairs",
+            "T55J5 as T5555: four of a kind",
+            "QQQJA as QQQQA: four of a kind",
+            "KTJJT as KTTTT: four of a kind",
+        ];
+        assert_eq!(expected.to_vec(), explained);
+
+        let expected = [
+            " Rank  Hand   As     Type                Bid   Winnings",
+            "    1  32T3K  32T3K  one pair            765        765",
+            "    2  KK677  KK677  two pairs            28         56",
+            "    3  T55J5  T5555  four of a kind      684       2052",
+            "    4  QQQJA  QQQQA  four of a kind      483       1932",
+            "    5  KTJJT  KTTTT  four of a kind      220       1100",
+            "Total                                              5905",
+        ];
+        assert_eq!(expected.join("\n"), table(&hands));
+    }
+
+    #[test]
+    fn test_part2() {
+        let actual = part2(EXAMPLE).unwrap();
         let expected = 5905;
 
         assert_eq!(expected, actual);
@@ -0,0 +1,167 @@
+use std::collections::HashMap;
+use std::hash::Hash;
+
+// A sequence `x0, f(x0), f(f(x0)), ...` that eventually repeats: after `prefix` steps, the states
+// repeat every `period` steps.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Cycle {
+    pub prefix: usize,
+    pub period: usize,
+}
+
+impl Cycle {
+    // Maps any step count to the smallest equivalent one (i.e. which leads to the same state).
+    pub fn reduce(self, steps: usize) -> usize {
+        if steps < self.prefix {
+            steps
+        } else {
+            self.prefix + (steps - self.prefix) % self.period
+        }
+    }
+}
+
+// Floyd's tortoise and hare. Only keeps three states in memory, at the cost of more steps.
+pub fn floyd<T, F>(initial: &T, mut step: F) -> Cycle
+where
+    T: Clone + Eq,
+    F: FnMut(&mut T),
+{
+    let mut tortoise = initial.clone();
+    let mut hare = initial.clone();
+
+    loop {
+        step(&mut tortoise);
+        step(&mut hare);
+        step(&mut hare);
+        if tortoise == hare {
+            break;
+        }
+    }
+
+    // The distance between the start and the meeting point is a multiple of the period.
+    let mut prefix = 0;
+    tortoise = initial.clone();
+    while tortoise != hare {
+        step(&mut tortoise);
+        step(&mut hare);
+        prefix += 1;
+    }
+
+    let mut period = 1;
+    step(&mut hare);
+    while tortoise != hare {
+        step(&mut hare);
+        period += 1;
+    }
+
+    Cycle { prefix, period }
+}
+
+// Brent's algorithm. Same memory footprint as Floyd's, but fewer steps.
+pub fn brent<T, F>(initial: &T, mut step: F) -> Cycle
+where
+    T: Clone + Eq,
+    F: FnMut(&mut T),
+{
+    let mut power = 1;
+    let mut period = 1;
+    let mut tortoise = initial.clone();
+    let mut hare = initial.clone();
+    step(&mut hare);
+
+    while tortoise != hare {
+        if power == period {
+            tortoise = hare.clone();
+            power *= 2;
+            period = 0;
+        }
+        step(&mut hare);
+        period += 1;
+    }
+
+    // Start the hare a full period ahead, then advance both until they meet.
+    tortoise = initial.clone();
+    hare = initial.clone();
+    for _ in 0..period {
+        step(&mut hare);
+    }
+
+    let mut prefix = 0;
+    while tortoise != hare {
+        step(&mut tortoise);
+        step(&mut hare);
+        prefix += 1;
+    }
+
+    Cycle { prefix, period }
+}
+
+// Remembers every state until one repeats. Each state is only computed once.
+pub fn hashed<T, F>(initial: &T, step: F) -> Cycle
+where
+    T: Clone + Hash + Eq,
+    F: FnMut(&mut T),
+{
+    history(initial, step).1
+}
+
+// The state after `steps` steps, skipping ahead once a cycle is found.
+pub fn state_after<T, F>(initial: &T, step: F, steps: usize) -> T
+where
+    T: Clone + Hash + Eq,
+    F: FnMut(&mut T),
+{
+    let (mut states, cycle) = history(initial, step);
+    states.swap_remove(cycle.reduce(steps))
+}
+
+fn history<T, F>(initial: &T, mut step: F) -> (Vec<T>, Cycle)
+where
+    T: Clone + Hash + Eq,
+    F: FnMut(&mut T),
+{
+    let mut seen = HashMap::new();
+    let mut states = Vec::new();
+    let mut current = initial.clone();
+
+    loop {
+        if let Some(&prefix) = seen.get(&current) {
+            let period = states.len() - prefix;
+            return (states, Cycle { prefix, period });
+        }
+
+        seen.insert(current.clone(), states.len());
+        states.push(current.clone());
+        step(&mut current);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 0 -> 1 -> 2 -> 3 -> 4 -> 5 -> 2 -> ...
+    fn step(x: &mut u32) {
+        *x = if *x == 5 { 2 } else { *x + 1 };
+    }
+
+    #[test]
+    fn test_detection() {
+        let expected = Cycle {
+            prefix: 2,
+            period: 4,
+        };
+
+        assert_eq!(expected, floyd(&0, step));
+        assert_eq!(expected, brent(&0, step));
+        assert_eq!(expected, hashed(&0, step));
+    }
+
+    #[test]
+    fn test_state_after() {
+        assert_eq!(1, state_after(&0, step, 1));
+        assert_eq!(5, state_after(&0, step, 5));
+        assert_eq!(2, state_after(&0, step, 6));
+        assert_eq!(3, state_after(&0, step, 1_000_000_003));
+    }
+}
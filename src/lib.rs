@@ -1,3 +1,4 @@
+pub mod cycle;
 pub mod grid;
 pub mod position;
 pub mod range_extension;